@@ -1,14 +1,20 @@
-use std::{
-    env::current_dir,
-    path::{PathBuf, absolute},
-};
+use std::path::PathBuf;
 
-use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 
+use crate::{export::Format, hook::HookShell, shell::Shell};
+
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
+    /// Maximum number of resources installed in parallel (defaults to $CHENV_JOBS or the number of CPUs)
+    #[arg(long, short, global = true)]
+    pub jobs: Option<usize>,
+
+    /// Never access the network: only use the store and file:// URLs (also CHENV_OFFLINE=1)
+    #[arg(long, global = true, default_value_t = false)]
+    pub offline: bool,
+
     #[command(subcommand)]
     pub cmd: Command,
 }
@@ -30,6 +36,73 @@ pub enum Command {
     Shell {
         /// Path to configuration file
         path: Option<PathBuf>,
+
+        /// Shell to start (defaults to $SHELL)
+        #[arg(long, value_enum)]
+        shell: Option<Shell>,
+    },
+    /// runs a command inside the environment
+    Exec {
+        /// Path to configuration file
+        #[arg(long)]
+        config: Option<PathBuf>,
+
+        /// Command to run, with its arguments
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        cmd: Vec<String>,
+    },
+    /// prints the environment variables
+    Env {
+        /// Path to configuration file
+        #[arg(long)]
+        config: Option<PathBuf>,
+
+        /// Output format
+        #[arg(long, short, value_enum, default_value_t = Format::Posix)]
+        format: Format,
+    },
+    /// prints a hook activating the environment when entering a directory
+    Hook {
+        /// Shell to print the hook for
+        #[arg(value_enum)]
+        shell: HookShell,
+    },
+    /// computes the environment changes for the hook
+    #[command(hide = true)]
+    HookEnv {
+        #[arg(value_enum)]
+        shell: HookShell,
+    },
+    /// removes store entries that are no longer used
+    Gc {
+        /// Only print what would be removed
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+
+        /// Also remove entries not used for this number of days
+        #[arg(long, value_name = "DAYS")]
+        older_than: Option<u64>,
+    },
+    /// checks store entries against the manifest recorded at install time
+    Verify {
+        /// Path to configuration file
+        #[arg(long)]
+        config: Option<PathBuf>,
+
+        /// Re-fetch corrupt entries
+        #[arg(long, default_value_t = false)]
+        fix: bool,
+    },
+    /// writes chenv.lock pinning resolved resources
+    Lock {
+        /// Path to configuration file
+        path: Option<PathBuf>,
+    },
+    /// installs the resources of the configuration without starting a shell
+    Fetch {
+        /// Path to configuration file
+        #[arg(long)]
+        config: Option<PathBuf>,
     },
 }
 
@@ -63,17 +136,3 @@ pub enum Lang {
 pub fn get_cli() -> Cli {
     Cli::parse()
 }
-
-impl Cli {
-    pub fn get_repository_path(&self) -> Result<PathBuf> {
-        match &self.cmd {
-            Command::Init { lang: _ } => Ok(current_dir()?),
-            Command::Code { path } => Ok(absolute(path)?),
-            Command::Shell { path: Some(path) } => Ok(absolute(path)?
-                .parent()
-                .with_context(|| format!("Configuration cannot be found as {:?}", path))?
-                .to_owned()),
-            Command::Shell { path: None } => Ok(current_dir()?),
-        }
-    }
-}
@@ -1,7 +1,12 @@
 use std::{
     collections::{HashMap, HashSet},
-    path::Path,
+    path::{Path, PathBuf},
     str::from_utf8,
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
 };
 
 use anyhow::{Context, Result, anyhow, bail};
@@ -10,9 +15,11 @@ use reqwest::redirect;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    Os,
+    Os, gc,
     interpol::{Env, InterpolableString},
-    resources::{self, Resource, Substrate},
+    lock::{self, Lock, LockedEnvironment, LockedResource},
+    resources::{self, OfflineError, Resource, Substrate, store::Store},
+    shell::Shell,
 };
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -73,18 +80,112 @@ impl Environment {
         Ok(result)
     }
 
-    pub fn ensure_resources(&self, config_parent: &Path) -> Result<Env> {
+    pub fn ensure_resources(
+        &self,
+        store: &Store,
+        lock: Option<&LockedEnvironment>,
+        jobs: usize,
+    ) -> Result<Env> {
+        let resolution = self.resolve(store, lock, jobs)?;
+        check_outcomes(resolution.outcomes)?;
+        Ok(resolution.env)
+    }
+
+    pub fn lock(&self, store: &Store, jobs: usize) -> Result<LockedEnvironment> {
+        let resolution = self.resolve(store, None, jobs)?;
+        check_outcomes(resolution.outcomes)?;
+        Ok(resolution.locked)
+    }
+
+    // installe toutes les ressources possibles et donne l'état de chacune, dans l'ordre de résolution
+    pub fn fetch(
+        &self,
+        store: &Store,
+        lock: Option<&LockedEnvironment>,
+        jobs: usize,
+    ) -> Result<Vec<(String, Outcome)>> {
+        Ok(self.resolve(store, lock, jobs)?.outcomes)
+    }
+
+    fn resolve(
+        &self,
+        store: &Store,
+        lock: Option<&LockedEnvironment>,
+        jobs: usize,
+    ) -> Result<Resolution> {
         let mut resources = Env::new();
+        let mut locked = LockedEnvironment::new();
+        let mut outcomes: Vec<(String, Outcome)> = Vec::new();
         resources.insert("host".to_string(), Substrate::new(Host::new()));
         if let Some(r) = &self.resources {
-            for (k, v) in order_dependences(r)? {
-                resources.insert(
-                    k.to_string(),
-                    v.ensure_resources(&resources, config_parent)?,
+            // les ressources d'une même couche sont indépendantes : on les installe en parallèle
+            for layer in layer_dependences(r)? {
+                // une ressource qui dépend d'une ressource non installée ne peut pas être résolue
+                let mut ready = Vec::new();
+                for (k, v) in layer {
+                    let unresolved = outcomes.iter().find(|(name, outcome)| {
+                        !outcome.is_installed() && v.get_dependances().contains(&name.as_str())
+                    });
+                    match unresolved {
+                        Some((_, Outcome::Missing)) => {
+                            outcomes.push((k.to_string(), Outcome::Missing))
+                        }
+                        Some((dep, _)) => {
+                            let e = anyhow!("Dependency {} could not be installed", dep);
+                            outcomes.push((k.to_string(), Outcome::Failed(e)));
+                        }
+                        None => ready.push((k, v)),
+                    }
+                }
+                // une ressource qu'on ne peut pas verrouiller, ou qui diffère du fichier de
+                // verrouillage, est en échec sans interrompre l'installation des autres
+                ready.retain(
+                    |(k, v)| match lock_resource(k, v, &resources, store, lock) {
+                        Ok(l) => {
+                            locked.extend(l);
+                            true
+                        }
+                        Err(e) => {
+                            outcomes.push((k.to_string(), Outcome::Failed(e)));
+                            false
+                        }
+                    },
                 );
+                let substrates =
+                    parallel(&ready, jobs, |(_, v)| v.ensure_resources(&resources, store));
+                for ((k, _), substrate) in ready.iter().zip(substrates) {
+                    let outcome = match substrate {
+                        Ok((substrate, installed)) => {
+                            resources.insert(k.to_string(), substrate);
+                            if installed {
+                                Outcome::Downloaded
+                            } else {
+                                Outcome::Reused
+                            }
+                        }
+                        Err(e) if e.is::<OfflineError>() => Outcome::Missing,
+                        Err(e) => Outcome::Failed(e),
+                    };
+                    outcomes.push((k.to_string(), outcome));
+                }
             }
         }
-        Ok(resources)
+        // les ressources non installées n'ont pas d'entrée dans locked
+        if outcomes.iter().all(|(_, outcome)| outcome.is_installed())
+            && let Some(lock) = lock
+            && let Some(k) = lock.keys().find(|k| !locked.contains_key(*k))
+        {
+            bail!(
+                "Resource {} is in {} but not in configuration, run `chenv lock` to update it",
+                k,
+                lock::LOCK_FILE
+            );
+        }
+        Ok(Resolution {
+            env: resources,
+            locked,
+            outcomes,
+        })
     }
 
     pub(crate) fn merge(self, other: Environment) -> Result<Self> {
@@ -109,6 +210,75 @@ impl Environment {
     }
 }
 
+// ressources installées, leur verrouillage et l'état de chacune dans l'ordre de résolution
+struct Resolution {
+    env: Env,
+    locked: LockedEnvironment,
+    outcomes: Vec<(String, Outcome)>,
+}
+
+// entrées du verrouillage d'une ressource, vérifiées contre le fichier de verrouillage s'il existe
+fn lock_resource(
+    name: &str,
+    resource: &Resource,
+    resources: &Env,
+    store: &Store,
+    lock: Option<&LockedEnvironment>,
+) -> Result<Vec<(String, LockedResource)>> {
+    let mut result = Vec::new();
+    for (platform, l) in resource.lock(resources, store)? {
+        let key = match platform {
+            Some(platform) => format!("{name}@{platform}"),
+            None => name.to_string(),
+        };
+        if let Some(lock) = lock {
+            lock::check(&key, lock.get(&key), &l)?;
+        }
+        result.push((key, l));
+    }
+    Ok(result)
+}
+
+// état d'une ressource après résolution
+pub enum Outcome {
+    Reused,
+    Downloaded,
+    // absente du store en mode hors ligne, ou dépendant d'une ressource absente
+    Missing,
+    Failed(anyhow::Error),
+}
+
+impl Outcome {
+    fn is_installed(&self) -> bool {
+        matches!(self, Outcome::Reused | Outcome::Downloaded)
+    }
+}
+
+// échoue sur la première erreur, ou en listant toutes les ressources absentes en mode hors ligne
+fn check_outcomes(outcomes: Vec<(String, Outcome)>) -> Result<()> {
+    let mut missing = Vec::new();
+    for (k, outcome) in outcomes {
+        match outcome {
+            Outcome::Failed(e) => return Err(e),
+            Outcome::Missing => missing.push(k),
+            Outcome::Reused | Outcome::Downloaded => {}
+        }
+    }
+    if !missing.is_empty() {
+        missing.sort();
+        bail!(
+            "Offline mode: {} missing from the store: {}",
+            if missing.len() == 1 {
+                "resource"
+            } else {
+                "resources"
+            },
+            missing.join(", ")
+        );
+    }
+    Ok(())
+}
+
 fn merge_maps<V>(map1: HashMap<String, V>, map2: HashMap<String, V>) -> Result<HashMap<String, V>>
 where
     V: Eq,
@@ -143,10 +313,11 @@ fn next_gen<'a>(
         .collect()
 }
 
-fn order_dependencies_gen<'a>(
+// regroupe les éléments par couches : chaque couche ne dépend que des couches précédentes
+fn layer_dependencies_gen<'a>(
     values: Vec<&'a str>,
     descendant: impl Fn(&'a str) -> Vec<&'a str>,
-) -> Result<Vec<&'a str>> {
+) -> Result<Vec<Vec<&'a str>>> {
     let mut deps: HashMap<&'a str, HashSet<&'a str>> = HashMap::from_iter(
         values
             .iter()
@@ -157,45 +328,73 @@ fn order_dependencies_gen<'a>(
         deps = deps_next;
         deps_next = next_gen(&deps, &descendant);
     }
+    let mut done: HashSet<&str> = HashSet::new();
     let mut result = Vec::new();
     // tant qu'il reste des éléments à ajouter
-    while values.iter().any(|k| result.iter().all(|name| name != k)) {
-        let mut has_new_element = false;
-        for k in values.iter() {
-            // si k n'est pas déjà dans result...
-            if result.iter().all(|name| name != k) {
-                // ...et si tous les dépendances de k sont dans result
-                if deps[k].iter().all(|a| result.iter().any(|name| name == a)) {
-                    has_new_element = true;
-                    result.push(*k);
-                }
-            }
-        }
-        // si on n'a pas ajouté d'élément alors qu'il reste des éléments à ajouter,
+    while values.iter().any(|k| !done.contains(k)) {
+        // éléments restants dont toutes les dépendances sont dans les couches précédentes
+        let layer = values
+            .iter()
+            .filter(|k| !done.contains(*k))
+            .filter(|k| deps[*k].iter().all(|a| done.contains(a)))
+            .copied()
+            .collect::<Vec<_>>();
+        // si la couche est vide alors qu'il reste des éléments à ajouter,
         // c'est qu'il y a une dépendance circulaire
-        if !has_new_element {
+        if layer.is_empty() {
             return Err(anyhow!("Circular dependences detected"));
         }
+        done.extend(&layer);
+        result.push(layer);
     }
-    return Ok(result);
+    Ok(result)
 }
 
-fn order_dependences<'a>(
-    resources: &'a HashMap<String, Resource>,
-) -> Result<Vec<(&'a str, &'a Resource)>> {
+fn layer_dependences(resources: &HashMap<String, Resource>) -> Result<Vec<Vec<(&str, &Resource)>>> {
     let keys = resources.keys().map(|k| k.as_str()).collect::<Vec<_>>();
-    let ordered_keys = order_dependencies_gen(keys.clone(), |k| resources[k].get_dependances())?;
-    Ok(ordered_keys
+    let layers = layer_dependencies_gen(keys, |k| resources[k].get_dependances())?;
+    Ok(layers
         .into_iter()
-        .map(|k| {
-            (
-                k,
-                resources.get(k).expect("All keys should be in resources"),
-            )
+        .map(|layer| {
+            layer
+                .into_iter()
+                .map(|k| {
+                    (
+                        k,
+                        resources.get(k).expect("All keys should be in resources"),
+                    )
+                })
+                .collect()
         })
         .collect())
 }
 
+// applique f à chaque élément avec au plus jobs threads, en conservant l'ordre des résultats
+fn parallel<T: Sync, R: Send>(items: &[T], jobs: usize, f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(items.iter().map(|_| None).collect::<Vec<_>>());
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(i) else {
+                        break;
+                    };
+                    let result = f(item);
+                    results.lock().expect("A job panicked")[i] = Some(result);
+                }
+            });
+        }
+    });
+    results
+        .into_inner()
+        .expect("A job panicked")
+        .into_iter()
+        .map(|r| r.expect("All items should be processed"))
+        .collect()
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct BuildEnvironment {
     pub cmd: String,
@@ -208,27 +407,82 @@ pub struct Conf {
     pub shell: Option<Environment>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub builder: Option<BuildEnvironment>,
+    // shell lancé par `chenv shell` (par défaut $SHELL)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell_program: Option<Shell>,
+    // marqueur ajouté au prompt, vide pour le désactiver
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+}
+
+impl Conf {
+    // environnements shell et builder, avec leur partie du fichier de verrouillage
+    pub fn environments<'a>(
+        &'a self,
+        lock: Option<&'a Lock>,
+    ) -> Vec<(&'static str, &'a Environment, Option<&'a LockedEnvironment>)> {
+        self.shell
+            .iter()
+            .map(|shell| ("shell", shell, lock.map(|l| &l.shell)))
+            .chain(
+                self.builder
+                    .iter()
+                    .map(|builder| ("builder", &builder.env, lock.map(|l| &l.builder))),
+            )
+            .collect()
+    }
 }
 
+pub const CONFIG_FILE: &str = "chenv.yaml";
+
 pub fn read_config(path: &Path) -> Result<Conf> {
     let file = std::fs::File::open(path)?;
-    Ok(serde_yaml::from_reader(file)?)
+    let conf = serde_yaml::from_reader(file)?;
+    // toute configuration lue devient une racine pour `chenv gc`
+    if let Err(e) = gc::register_root(path) {
+        log::warn!("Cannot register {:?} for garbage collection: {}", path, e);
+    }
+    Ok(conf)
 }
 
-pub fn read_config_in_repo(path: &Path) -> Result<Conf> {
-    let file = std::fs::File::open(path.join("chenv.yaml"))?;
-    Ok(serde_yaml::from_reader(file)?)
+// cherche la configuration dans path puis dans ses parents, comme git pour .git
+pub fn read_config_in_repo(path: &Path) -> Result<(Conf, PathBuf)> {
+    let dir = find_config_dir(path)
+        .with_context(|| format!("Cannot find {} in {:?} or its parents", CONFIG_FILE, path))?;
+    let config_path = dir.join(CONFIG_FILE);
+    eprintln!("Config: {:?}", config_path);
+    Ok((read_config(&config_path)?, dir))
+}
+
+pub fn find_config_dir(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| dir.join(CONFIG_FILE).is_file())
+        .map(|dir| dir.to_owned())
 }
 
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
 
+    #[test]
+    fn test_find_config_dir() -> Result<()> {
+        use super::{CONFIG_FILE, find_config_dir};
+
+        let tmp = tempfile::tempdir()?;
+        let nested = tmp.path().join("a").join("b");
+        std::fs::create_dir_all(&nested)?;
+        assert_eq!(find_config_dir(&nested), None);
+        std::fs::write(tmp.path().join("a").join(CONFIG_FILE), "")?;
+        assert_eq!(find_config_dir(&nested), Some(tmp.path().join("a")));
+        Ok(())
+    }
+
     #[test]
     fn test_order_dependencies_same_length_path() -> Result<()> {
         use std::collections::HashMap;
 
-        use super::order_dependencies_gen;
+        use super::layer_dependencies_gen;
 
         let a = "a";
         let b = "b";
@@ -247,13 +501,16 @@ mod tests {
         .into_iter()
         .collect::<HashMap<_, _>>();
 
-        let result = order_dependencies_gen(values, |k| {
+        let result = layer_dependencies_gen(values, |k| {
             deps.get(k)
                 .expect("All keys should be in deps")
                 .iter()
                 .map(|v| *v)
                 .collect()
-        })?;
+        })?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
 
         assert!(result == vec!["a", "d", "c", "b", "e"] || result == vec!["a", "d", "b", "c", "e"]);
         Ok(())
@@ -263,7 +520,7 @@ mod tests {
     fn test_order_dependencies_diff_length_path() -> Result<()> {
         use std::collections::HashMap;
 
-        use super::order_dependencies_gen;
+        use super::layer_dependencies_gen;
 
         let a = "a";
         let b = "b";
@@ -284,13 +541,16 @@ mod tests {
         .into_iter()
         .collect::<HashMap<_, _>>();
 
-        let result = order_dependencies_gen(values, |k| {
+        let result = layer_dependencies_gen(values, |k| {
             deps.get(k)
                 .expect("All keys should be in deps")
                 .iter()
                 .map(|v| *v)
                 .collect()
-        })?;
+        })?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
 
         assert!(
             result == vec!["f", "e", "d", "c", "b", "a"]
@@ -303,7 +563,7 @@ mod tests {
     fn test_order_dependencies_2_roots() -> Result<()> {
         use std::collections::HashMap;
 
-        use super::order_dependencies_gen;
+        use super::layer_dependencies_gen;
 
         let a = "a";
         let b = "b";
@@ -322,13 +582,16 @@ mod tests {
         .into_iter()
         .collect::<HashMap<_, _>>();
 
-        let result = order_dependencies_gen(values, |k| {
+        let result = layer_dependencies_gen(values, |k| {
             deps.get(k)
                 .expect("All keys should be in deps")
                 .iter()
                 .map(|v| *v)
                 .collect()
-        })?;
+        })?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
 
         assert!(result == vec!["e", "c", "d", "b", "a"] || result == vec!["e", "c", "d", "a", "b"]);
         Ok(())
@@ -338,7 +601,7 @@ mod tests {
     fn test_order_dependencies_cycle() -> Result<()> {
         use std::collections::HashMap;
 
-        use super::order_dependencies_gen;
+        use super::layer_dependencies_gen;
 
         let a = "a";
         let b = "b";
@@ -357,7 +620,7 @@ mod tests {
         .into_iter()
         .collect::<HashMap<_, _>>();
 
-        let result = order_dependencies_gen(values, |k| {
+        let result = layer_dependencies_gen(values, |k| {
             deps.get(k)
                 .expect("All keys should be in deps")
                 .iter()
@@ -369,4 +632,142 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_layer_dependencies() -> Result<()> {
+        use std::collections::HashMap;
+
+        use super::{layer_dependencies_gen, parallel};
+
+        let deps = HashMap::from([
+            ("kubectl", vec![]),
+            ("k9s", vec![]),
+            ("jdk", vec![]),
+            ("maven", vec!["jdk"]),
+            ("plugin", vec!["maven", "k9s"]),
+        ]);
+        let values = vec!["kubectl", "k9s", "jdk", "maven", "plugin"];
+        let layers = layer_dependencies_gen(values, |k| deps[k].clone())?;
+        assert_eq!(
+            layers,
+            vec![vec!["kubectl", "k9s", "jdk"], vec!["maven"], vec!["plugin"]]
+        );
+
+        let items = (0..10).collect::<Vec<_>>();
+        assert_eq!(
+            parallel(&items, 3, |i| i * 2),
+            (0..20).step_by(2).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_offline_lists_missing_resources() -> Result<()> {
+        use super::Environment;
+        use crate::{resources::store::Store, test_utils::tool};
+
+        let tmp = tempfile::tempdir()?;
+        let tool = tool(tmp.path())?;
+        let env: Environment = serde_yaml::from_str(&format!(
+            r#"
+resources:
+  local: !File
+    url: {local}
+    name: tool
+    sha256: {digest}
+  jdk: !File
+    url: https://example.com/jdk.tar.gz
+    name: jdk
+    sha256: {digest}
+  maven: !File
+    url: https://example.com/maven-${{jdk}}.tar.gz
+    name: maven
+    sha256: {digest}
+"#,
+            local = tool.url,
+            digest = tool.sha256,
+        ))?;
+        let store = Store::new(tmp.path(), None).with_offline(true);
+        let err = env.ensure_resources(&store, None, 1).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Offline mode: resources missing from the store: jdk, maven"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fetch_outcomes() -> Result<()> {
+        use super::{Environment, Outcome};
+        use crate::{resources::store::Store, test_utils::tool};
+
+        let tmp = tempfile::tempdir()?;
+        let tool = tool(tmp.path())?;
+        let env: Environment = serde_yaml::from_str(&format!(
+            r#"
+resources:
+  local: !File
+    url: {local}
+    name: tool
+    sha256: {digest}
+  broken: !File
+    url: {broken}
+    name: broken
+    sha256: {digest}
+  plugin: !File
+    url: file:///${{broken}}/plugin
+    name: plugin
+    sha256: {digest}
+"#,
+            local = tool.url,
+            digest = tool.sha256,
+            broken = url::Url::from_file_path(tmp.path().join("missing.sh")).unwrap(),
+        ))?;
+        let store = Store::new(tmp.path(), None);
+        let summary = |outcomes: Vec<(String, Outcome)>| {
+            let mut summary = outcomes
+                .into_iter()
+                .map(|(k, outcome)| {
+                    let outcome = match outcome {
+                        Outcome::Reused => "reused",
+                        Outcome::Downloaded => "downloaded",
+                        Outcome::Missing => "missing",
+                        Outcome::Failed(_) => "failed",
+                    };
+                    format!("{k}: {outcome}")
+                })
+                .collect::<Vec<_>>();
+            summary.sort();
+            summary
+        };
+        assert_eq!(
+            summary(env.fetch(&store, None, 1)?),
+            vec!["broken: failed", "local: downloaded", "plugin: failed"]
+        );
+        assert_eq!(
+            summary(env.fetch(&store, None, 1)?),
+            vec!["broken: failed", "local: reused", "plugin: failed"]
+        );
+        assert!(env.ensure_resources(&store, None, 1).is_err());
+
+        // une ressource absente du fichier de verrouillage n'empêche pas d'installer les autres
+        let tools = |names: &[&str]| -> Result<Environment> {
+            let resources = names
+                .iter()
+                .map(|name| {
+                    format!(
+                        "  {name}: !File\n    url: {}\n    name: {name}\n    sha256: {}\n",
+                        tool.url, tool.sha256
+                    )
+                })
+                .collect::<String>();
+            Ok(serde_yaml::from_str(&format!("resources:\n{resources}"))?)
+        };
+        let lock = tools(&["local"])?.lock(&store, 1)?;
+        assert_eq!(
+            summary(tools(&["local", "unlocked"])?.fetch(&store, Some(&lock), 1)?),
+            vec!["local: reused", "unlocked: failed"]
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,129 @@
+use std::{collections::BTreeMap, env::split_paths};
+
+use anyhow::{Result, bail};
+use clap::ValueEnum;
+
+use crate::Os;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// export KEY='value' (bash, zsh, sh)
+    Posix,
+    /// set -gx KEY 'value'
+    Fish,
+    /// $env:KEY = 'value'
+    Powershell,
+    /// KEY="value"
+    Dotenv,
+    /// {"KEY": "value"}
+    Json,
+    /// export KEY := value (Makefile include)
+    Make,
+}
+
+pub fn posix_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+pub fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn powershell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn dotenv_quote(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            _ => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn make_escape(k: &str, s: &str) -> Result<String> {
+    if s.contains('\n') {
+        bail!(
+            "Variable {} contains a newline, which cannot be written in a Makefile",
+            k
+        )
+    }
+    Ok(s.replace('$', "$$").replace('#', "\\#"))
+}
+
+pub fn export_var(format: Format, k: &str, v: &str) -> Result<String> {
+    Ok(match format {
+        Format::Posix => format!("export {}={}\n", k, posix_quote(v)),
+        // PATH est une liste pour fish
+        Format::Fish if k == Os::get().get_path() => format!(
+            "set -gx {} {}\n",
+            k,
+            split_paths(v)
+                .map(|p| fish_quote(&p.to_string_lossy()))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+        Format::Fish => format!("set -gx {} {}\n", k, fish_quote(v)),
+        Format::Powershell => format!("$env:{} = {}\n", k, powershell_quote(v)),
+        Format::Dotenv => format!("{}={}\n", k, dotenv_quote(v)),
+        Format::Json => format!("{}\n", serde_json::to_string(&BTreeMap::from([(k, v)]))?),
+        Format::Make => format!("export {} := {}\n", k, make_escape(k, v)?),
+    })
+}
+
+pub fn export(format: Format, vars: &BTreeMap<String, String>) -> Result<String> {
+    if format == Format::Json {
+        return Ok(format!("{}\n", serde_json::to_string_pretty(vars)?));
+    }
+    let mut result = String::new();
+    for (k, v) in vars {
+        result.push_str(&export_var(format, k, v)?);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use anyhow::Result;
+
+    use super::{Format, export};
+
+    #[test]
+    fn test_export_quoting() -> Result<()> {
+        let vars = BTreeMap::from([("FOO".to_string(), "it's $HOME \"x\"".to_string())]);
+        assert_eq!(
+            export(Format::Posix, &vars)?,
+            "export FOO='it'\\''s $HOME \"x\"'\n"
+        );
+        assert_eq!(
+            export(Format::Fish, &vars)?,
+            "set -gx FOO 'it\\'s $HOME \"x\"'\n"
+        );
+        assert_eq!(
+            export(Format::Powershell, &vars)?,
+            "$env:FOO = 'it''s $HOME \"x\"'\n"
+        );
+        assert_eq!(
+            export(Format::Dotenv, &vars)?,
+            "FOO=\"it's $HOME \\\"x\\\"\"\n"
+        );
+        assert_eq!(
+            export(Format::Make, &vars)?,
+            "export FOO := it's $$HOME \"x\"\n"
+        );
+        assert_eq!(
+            serde_json::from_str::<BTreeMap<String, String>>(&export(Format::Json, &vars)?)?,
+            vars
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,43 @@
+use anyhow::{Result, bail};
+
+use crate::{
+    config::{Conf, Outcome},
+    lock::Lock,
+    resources::store::Store,
+};
+
+// installe les ressources des environnements shell et builder sans lancer de shell, par exemple
+// pour remplir le store lors de la construction d'une image
+pub fn fetch(conf: &Conf, store: &Store, lock: Option<&Lock>, jobs: usize) -> Result<()> {
+    let (mut downloaded, mut reused, mut failed) = (0, 0, 0);
+    for (name, env, lock) in conf.environments(lock) {
+        for (resource, outcome) in env.fetch(store, lock, jobs)? {
+            match outcome {
+                Outcome::Downloaded => {
+                    println!("Downloaded: {} ({})", resource, name);
+                    downloaded += 1;
+                }
+                Outcome::Reused => {
+                    println!("Reused: {} ({})", resource, name);
+                    reused += 1;
+                }
+                Outcome::Missing => {
+                    println!("Failed: {} ({}): not available offline", resource, name);
+                    failed += 1;
+                }
+                Outcome::Failed(e) => {
+                    println!("Failed: {} ({}): {:#}", resource, name, e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+    println!(
+        "{} downloaded, {} reused, {} failed",
+        downloaded, reused, failed
+    );
+    if failed > 0 {
+        bail!("{} resources could not be fetched", failed);
+    }
+    Ok(())
+}
@@ -0,0 +1,261 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs,
+    io::Write,
+    path::{Path, PathBuf, absolute},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    config,
+    resources::store::{self, Store},
+    user_config::UserConf,
+};
+
+// le store global peut être partagé (CHENV_STORE) avec d'autres utilisateurs, dont les
+// configurations ne sont pas dans notre liste : une entrée qui n'y est pas référencée n'est
+// supprimée qu'après ce délai sans utilisation
+const GLOBAL_GRACE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+// liste des configurations connues : toutes celles lues par chenv
+fn roots_path() -> Option<PathBuf> {
+    dirs::data_local_dir().map(|dir| dir.join("chenv").join("roots"))
+}
+
+fn read_roots() -> Result<BTreeSet<PathBuf>> {
+    match roots_path() {
+        Some(path) if path.exists() => Ok(fs::read_to_string(path)?
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(PathBuf::from)
+            .collect()),
+        _ => Ok(BTreeSet::new()),
+    }
+}
+
+// modifie la liste sous verrou, puis la remplace d'un coup : les processus concurrents ne perdent
+// pas leurs ajouts et gc ne lit jamais une liste partielle
+fn update_roots(update: impl FnOnce(&mut BTreeSet<PathBuf>) -> bool) -> Result<()> {
+    let path = roots_path().context("Cannot find user data directory")?;
+    let parent = path.parent().context("Cannot find user data directory")?;
+    fs::create_dir_all(parent)?;
+    let lock = fs::File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(parent.join("roots.lock"))?;
+    lock.lock()
+        .context("Cannot lock the list of configurations")?;
+    let mut roots = read_roots()?;
+    if update(&mut roots) {
+        let mut file = tempfile::NamedTempFile::new_in(parent)?;
+        for root in &roots {
+            writeln!(file, "{}", root.to_string_lossy())?;
+        }
+        file.persist(&path)?;
+    }
+    Ok(())
+}
+
+pub fn register_root(config_path: &Path) -> Result<()> {
+    let config_path = absolute(config_path)?;
+    if read_roots()?.contains(&config_path) {
+        return Ok(());
+    }
+    update_roots(|roots| roots.insert(config_path))
+}
+
+fn size(path: &Path) -> Result<u64> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        let mut total = 0;
+        for entry in fs::read_dir(path)? {
+            total += size(&entry?.path())?;
+        }
+        Ok(total)
+    } else {
+        Ok(metadata.len())
+    }
+}
+
+pub fn human_size(size: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", size, units[0])
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+// supprime les entrées des stores qui ne sont pas référencées et pas utilisées depuis grace, ou
+// qui n'ont pas été utilisées depuis older_than ; sans références connues, seules les entrées
+// expirées sont supprimées. Retourne les entrées supprimées et leur taille
+pub fn collect(
+    stores: &BTreeSet<PathBuf>,
+    referenced: Option<&HashSet<PathBuf>>,
+    grace: Duration,
+    older_than: Option<Duration>,
+    dry_run: bool,
+) -> Result<Vec<(PathBuf, u64)>> {
+    let now = SystemTime::now();
+    let mut removed = Vec::new();
+    for store in stores {
+        for entry in store::list_entries(store)? {
+            let idle = now
+                .duration_since(store::last_used(&entry)?)
+                .unwrap_or_default();
+            let unused =
+                referenced.is_some_and(|referenced| !referenced.contains(&entry)) && idle >= grace;
+            let expired = older_than.is_some_and(|older_than| idle > older_than);
+            if unused || expired {
+                let size = size(&entry)?;
+                if !dry_run {
+                    let Some(_lock) = store::try_lock_entry(&entry)? else {
+                        eprintln!("Skipped: {:?} is in use by another process", entry);
+                        continue;
+                    };
+                    store::remove_entry(&entry)
+                        .with_context(|| format!("Cannot remove {:?}", entry))?;
+                }
+                removed.push((entry, size));
+            }
+        }
+    }
+    Ok(removed)
+}
+
+pub fn gc(user_conf: &UserConf, older_than_days: Option<u64>, dry_run: bool) -> Result<()> {
+    let roots = read_roots()?;
+    let global = user_conf.global_store().map(absolute).transpose()?;
+    let mut stores = BTreeSet::new();
+    let mut referenced = HashSet::new();
+    let mut missing = Vec::new();
+    // une configuration illisible peut référencer n'importe quelle entrée du store global
+    let mut unreadable = false;
+    for root in &roots {
+        let Some(config_parent) = root.parent() else {
+            continue;
+        };
+        let store = Store::new(config_parent, user_conf.global_store());
+        if !root.exists() {
+            stores.insert(absolute(store.default_location())?);
+            missing.push(root.clone());
+            continue;
+        }
+        let conf = match config::read_config(root) {
+            Ok(conf) => conf,
+            Err(e) => {
+                eprintln!("Warning: keeping the entries of {:?}: {:#}", root, e);
+                unreadable = true;
+                continue;
+            }
+        };
+        stores.insert(absolute(store.default_location())?);
+        for (_, env, _) in conf.environments(None) {
+            for resource in env.resources.iter().flat_map(|r| r.values()) {
+                for entry in resource.entries(&store)? {
+                    let entry = absolute(entry)?;
+                    if let Some(parent) = entry.parent()
+                        && store::is_store(parent)
+                    {
+                        stores.insert(parent.to_owned());
+                    }
+                    referenced.insert(entry);
+                }
+            }
+        }
+    }
+    let older_than = older_than_days.map(|days| Duration::from_secs(days * 24 * 60 * 60));
+    let mut removed = Vec::new();
+    if let Some(global) = global {
+        stores.remove(&global);
+        // sans aucune configuration lue, rien ne dit quelles entrées sont encore utilisées
+        let known = !unreadable && roots.len() > missing.len();
+        removed.extend(collect(
+            &BTreeSet::from([global]),
+            known.then_some(&referenced),
+            GLOBAL_GRACE,
+            older_than,
+            dry_run,
+        )?);
+    }
+    removed.extend(collect(
+        &stores,
+        Some(&referenced),
+        Duration::ZERO,
+        older_than,
+        dry_run,
+    )?);
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    for (entry, size) in &removed {
+        println!("{}: {:?} ({})", verb, entry, human_size(*size));
+    }
+    let total = removed.iter().map(|(_, size)| size).sum();
+    println!("{} {} entries, {}", verb, removed.len(), human_size(total));
+    if !dry_run && !missing.is_empty() {
+        update_roots(|roots| {
+            let before = roots.len();
+            roots.retain(|root| !missing.contains(root));
+            roots.len() != before
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeSet, HashSet},
+        fs,
+        time::Duration,
+    };
+
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::collect;
+    use crate::resources::store;
+
+    #[test]
+    fn test_collect() -> Result<()> {
+        let tmp = tempdir()?;
+        let kept = tmp.path().join("kept");
+        let unused = tmp.path().join("unused");
+        let old = tmp.path().join("0123456789abcdef");
+        fs::create_dir_all(&kept)?;
+        fs::create_dir_all(&unused)?;
+        fs::create_dir_all(&old)?;
+        fs::write(unused.join("file"), "0123456789")?;
+        store::touch(&unused)?;
+        // un repo_location peut être la racine du dépôt : ce qui n'est pas une entrée reste
+        fs::create_dir_all(tmp.path().join("src"))?;
+        fs::write(tmp.path().join("Cargo.toml"), "")?;
+        let stores = BTreeSet::from([tmp.path().to_owned()]);
+        let referenced = HashSet::from([kept.clone()]);
+
+        let removed = collect(&stores, Some(&referenced), Duration::ZERO, None, true)?;
+        assert_eq!(removed, vec![(old.clone(), 0), (unused.clone(), 10)]);
+        assert!(unused.exists());
+        assert!(collect(&stores, None, Duration::ZERO, None, true)?.is_empty());
+        // store global : les entrées non référencées mais récentes sont conservées
+        let grace = Duration::from_secs(60 * 60);
+        assert!(collect(&stores, Some(&referenced), grace, None, true)?.is_empty());
+
+        let removed = collect(&stores, Some(&referenced), Duration::ZERO, None, false)?;
+        assert_eq!(removed, vec![(old.clone(), 0), (unused.clone(), 10)]);
+        assert!(!unused.exists());
+        assert!(!store::used_marker(&unused).unwrap().exists());
+        assert!(kept.exists());
+        assert!(tmp.path().join("src").exists());
+        assert!(tmp.path().join("Cargo.toml").exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    collections::BTreeMap,
+    env::{self, current_dir, current_exe},
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use anyhow::Result;
+use clap::ValueEnum;
+
+use crate::{
+    Options, config,
+    export::{self, Format, fish_quote, posix_quote},
+    get_vars,
+};
+
+// répertoire de la configuration active
+const CHENV_DIR: &str = "CHENV_DIR";
+// valeurs des variables avant activation (null si elles n'étaient pas définies)
+const CHENV_BACKUP: &str = "CHENV_BACKUP";
+// configuration dont le chargement a échoué : on ne le retente pas à chaque invite, seulement
+// quand le répertoire ou la date de modification de la configuration change
+const CHENV_FAILED: &str = "CHENV_FAILED";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HookShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl HookShell {
+    fn format(&self) -> Format {
+        match self {
+            HookShell::Bash => Format::Posix,
+            HookShell::Zsh => Format::Posix,
+            HookShell::Fish => Format::Fish,
+        }
+    }
+
+    fn unset_var(&self, k: &str) -> String {
+        match self {
+            HookShell::Bash => format!("unset {}\n", k),
+            HookShell::Zsh => format!("unset {}\n", k),
+            HookShell::Fish => format!("set -e {}\n", k),
+        }
+    }
+}
+
+pub fn hook(shell: HookShell) -> Result<String> {
+    let exe = current_exe()?.to_string_lossy().to_string();
+    Ok(match shell {
+        HookShell::Bash => format!(
+            r#"_chenv_hook() {{
+  local previous_exit_status=$?
+  eval "$({} hook-env bash)"
+  return $previous_exit_status
+}}
+if [[ ";${{PROMPT_COMMAND[*]:-}};" != *";_chenv_hook;"* ]]; then
+  PROMPT_COMMAND="_chenv_hook${{PROMPT_COMMAND:+;$PROMPT_COMMAND}}"
+fi
+"#,
+            posix_quote(&exe)
+        ),
+        HookShell::Zsh => format!(
+            r#"_chenv_hook() {{
+  eval "$({} hook-env zsh)"
+}}
+typeset -ag precmd_functions
+if (( ! ${{precmd_functions[(I)_chenv_hook]}} )); then
+  precmd_functions=(_chenv_hook $precmd_functions)
+fi
+typeset -ag chpwd_functions
+if (( ! ${{chpwd_functions[(I)_chenv_hook]}} )); then
+  chpwd_functions=(_chenv_hook $chpwd_functions)
+fi
+"#,
+            posix_quote(&exe)
+        ),
+        HookShell::Fish => format!(
+            r#"function __chenv_hook --on-event fish_prompt
+    {} hook-env fish | source
+end
+"#,
+            fish_quote(&exe)
+        ),
+    })
+}
+
+pub fn hook_env(shell: HookShell, options: Options) -> Result<String> {
+    let current = config::find_config_dir(&current_dir()?);
+    let active = env::var_os(CHENV_DIR).map(PathBuf::from);
+    let marker = current.as_deref().map(failure_marker);
+    let mut script = String::new();
+    if let Ok(failed) = env::var(CHENV_FAILED) {
+        if Some(&failed) == marker.as_ref() {
+            return Ok(script);
+        }
+        script.push_str(&shell.unset_var(CHENV_FAILED));
+    }
+    if current == active {
+        return Ok(script);
+    }
+    let backup: BTreeMap<String, Option<String>> = match env::var(CHENV_BACKUP) {
+        Ok(backup) => serde_json::from_str(&backup)?,
+        Err(_) => BTreeMap::new(),
+    };
+    for (k, v) in &backup {
+        match v {
+            Some(v) => {
+                script.push_str(&export::export_var(shell.format(), k, v)?);
+                // SAFETY: hook-env est mono-thread à ce stade
+                unsafe { env::set_var(k, v) };
+            }
+            None => {
+                script.push_str(&shell.unset_var(k));
+                // SAFETY: hook-env est mono-thread à ce stade
+                unsafe { env::remove_var(k) };
+            }
+        }
+    }
+    match current {
+        Some(dir) => match load(shell, &dir, options) {
+            Ok(load) => script.push_str(&load),
+            // l'environnement précédent est tout de même restauré
+            Err(e) => {
+                eprintln!("chenv: {:#}", e);
+                script.push_str(&shell.unset_var(CHENV_DIR));
+                script.push_str(&shell.unset_var(CHENV_BACKUP));
+                if let Some(marker) = &marker {
+                    script.push_str(&export::export_var(shell.format(), CHENV_FAILED, marker)?);
+                }
+            }
+        },
+        None => {
+            eprintln!("chenv: unloading");
+            script.push_str(&shell.unset_var(CHENV_DIR));
+            script.push_str(&shell.unset_var(CHENV_BACKUP));
+        }
+    }
+    Ok(script)
+}
+
+// répertoire et date de modification de sa configuration
+fn failure_marker(dir: &Path) -> String {
+    let modified = fs::metadata(dir.join(config::CONFIG_FILE))
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("{}:{}", modified, dir.to_string_lossy())
+}
+
+// script activant la configuration de dir
+fn load(shell: HookShell, dir: &Path, options: Options) -> Result<String> {
+    let (conf, dir) = config::read_config_in_repo(dir)?;
+    let vars = get_vars(&conf, &dir, options)?;
+    let backup = vars
+        .keys()
+        .map(|k| (k.clone(), env::var(k).ok()))
+        .collect::<BTreeMap<_, _>>();
+    let mut script = String::new();
+    for (k, v) in &vars {
+        script.push_str(&export::export_var(shell.format(), k, v)?);
+    }
+    script.push_str(&export::export_var(
+        shell.format(),
+        CHENV_DIR,
+        &dir.to_string_lossy(),
+    )?);
+    script.push_str(&export::export_var(
+        shell.format(),
+        CHENV_BACKUP,
+        &serde_json::to_string(&backup)?,
+    )?);
+    Ok(script)
+}
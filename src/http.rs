@@ -0,0 +1,178 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result, bail};
+use reqwest::{
+    Certificate, Identity, NoProxy, Proxy,
+    blocking::{Client, ClientBuilder},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::user_config::UserConf;
+
+// réglages TLS de la configuration utilisateur, par exemple derrière un proxy d'inspection TLS
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tls {
+    // certificats d'autorité supplémentaires (PEM, plusieurs par fichier possibles)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ca_files: Vec<PathBuf>,
+    // utilise aussi le magasin de certificats du système
+    #[serde(default = "default_true")]
+    pub system_roots: bool,
+    // certificat client (PEM) et sa clé (PEM PKCS#8) pour l'authentification mutuelle
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<PathBuf>,
+}
+
+impl Default for Tls {
+    fn default() -> Self {
+        Self {
+            ca_files: Vec::new(),
+            system_roots: true,
+            client_cert: None,
+            client_key: None,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+// point de départ de tous les clients HTTP, pour les téléchargements comme pour `chenv init`.
+// Le proxy de la ressource est prioritaire sur celui de la configuration utilisateur ; sans
+// l'un ni l'autre, reqwest utilise HTTP_PROXY, HTTPS_PROXY et NO_PROXY.
+pub fn builder(user_conf: &UserConf, proxy: Option<&str>) -> Result<ClientBuilder> {
+    let tls = &user_conf.tls;
+    let mut builder = Client::builder()
+        .user_agent("chenv")
+        .tls_built_in_root_certs(tls.system_roots);
+    // l'url du proxy peut contenir des identifiants : elle n'apparaît pas dans les erreurs
+    match (proxy, &user_conf.proxy) {
+        (Some(proxy), _) => {
+            builder = builder.proxy(Proxy::all(proxy).context("Invalid resource proxy")?);
+        }
+        (None, Some(proxy)) => {
+            let no_proxy = match &user_conf.no_proxy {
+                Some(no_proxy) => NoProxy::from_string(no_proxy),
+                None => NoProxy::from_env(),
+            };
+            builder = builder.proxy(
+                Proxy::all(proxy)
+                    .context("Invalid proxy in user configuration")?
+                    .no_proxy(no_proxy),
+            );
+        }
+        (None, None) => {}
+    }
+    for ca_file in &tls.ca_files {
+        let pem = fs::read(ca_file).with_context(|| format!("Cannot read {:?}", ca_file))?;
+        for certificate in Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("Invalid certificate in {:?}", ca_file))?
+        {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    match (&tls.client_cert, &tls.client_key) {
+        (Some(cert), Some(key)) => {
+            let cert_pem = fs::read(cert).with_context(|| format!("Cannot read {:?}", cert))?;
+            let key_pem = fs::read(key).with_context(|| format!("Cannot read {:?}", key))?;
+            let identity = Identity::from_pkcs8_pem(&cert_pem, &key_pem)
+                .with_context(|| format!("Invalid client certificate {:?}", cert))?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => bail!("tls.client_cert and tls.client_key must be set together"),
+    }
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use anyhow::Result;
+
+    use super::{Tls, builder};
+    use crate::user_config::UserConf;
+
+    #[test]
+    fn test_builder_rejects_incomplete_tls() {
+        let conf = UserConf {
+            tls: Tls {
+                client_cert: Some("client.pem".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(builder(&conf, None).is_err());
+        let conf = UserConf {
+            tls: Tls {
+                ca_files: vec!["/nonexistent/ca.pem".into()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(builder(&conf, None).is_err());
+        assert!(builder(&UserConf::default(), None).is_ok_and(|b| b.build().is_ok()));
+    }
+
+    // le proxy de la ressource passe avant celui de l'utilisateur, qui respecte no_proxy
+    #[test]
+    fn test_proxy_precedence() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let proxy = format!("http://{}", listener.local_addr()?);
+        let server = thread::spawn(move || -> Vec<String> {
+            let mut requests = Vec::new();
+            for stream in listener.incoming().take(2) {
+                let mut stream = stream.unwrap();
+                let mut request_line = String::new();
+                BufReader::new(&stream)
+                    .read_line(&mut request_line)
+                    .unwrap();
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                    .unwrap();
+                requests.push(request_line.trim().to_string());
+            }
+            requests
+        });
+        let conf = UserConf {
+            proxy: Some(proxy.clone()),
+            no_proxy: Some("direct.invalid".to_string()),
+            ..Default::default()
+        };
+        builder(&conf, None)?
+            .build()?
+            .get("http://global.invalid/a")
+            .send()?;
+        let unused = UserConf {
+            proxy: Some("http://127.0.0.1:1".to_string()),
+            ..Default::default()
+        };
+        builder(&unused, Some(&proxy))?
+            .build()?
+            .get("http://resource.invalid/b")
+            .send()?;
+        assert!(
+            builder(&conf, None)?
+                .build()?
+                .get("http://direct.invalid/c")
+                .send()
+                .is_err()
+        );
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                "GET http://global.invalid/a HTTP/1.1",
+                "GET http://resource.invalid/b HTTP/1.1"
+            ]
+        );
+        Ok(())
+    }
+}
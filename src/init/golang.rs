@@ -3,14 +3,16 @@ use jsonpath_rust::JsonPath;
 use reqwest::redirect;
 
 use crate::{
-    Os,
+    Arch, Os, Platform,
     config::{Environment, PathEnv},
+    http,
     interpol::InterpolableString,
     resources::{self, Resource},
+    user_config::read_user_config,
 };
 
-pub(crate) fn go() -> Result<Environment> {
-    let client_with_redirect = reqwest::blocking::Client::builder()
+pub(crate) fn go(platform: Platform) -> Result<Environment> {
+    let client_with_redirect = http::builder(&read_user_config()?, None)?
         .redirect(redirect::Policy::default())
         .build()?;
 
@@ -45,21 +47,26 @@ pub(crate) fn go() -> Result<Environment> {
         .and_then(|v| v.as_str())
         .context("Failed to find the latest stable Go version")?;
 
-    let os_str = match Os::get() {
+    let os_str = match platform.os {
         Os::Linux => "linux",
         Os::MacOS => "darwin",
         Os::Windows => "windows",
     };
 
-    let archive_fmt = match Os::get() {
+    let archive_fmt = match platform.os {
         Os::Linux => "tar.gz",
         Os::MacOS => "tar.gz",
         Os::Windows => "zip",
     };
 
+    let arch_str = match platform.arch {
+        Arch::X86_64 => "amd64",
+        Arch::Aarch64 => "arm64",
+    };
+
     let go_latest_sha256_all = go_latest
         .query(&format!(
-            "$.files[?@.os == \"{os_str}\" && @.arch == \"amd64\"].sha256"
+            "$.files[?@.os == \"{os_str}\" && @.arch == \"{arch_str}\"].sha256"
         ))
         .context("Failed to find the sha256 of the latest stable Go version")?;
     let go_latest_sha256 = go_latest_sha256_all
@@ -71,7 +78,7 @@ pub(crate) fn go() -> Result<Environment> {
     let go_latest_trimmed = go_latest_version.trim_start_matches('v'); // Supprimer le préfixe 'v'
 
     // Construire les URLs pour le binaire et le checksum
-    let go_url = format!("https://go.dev/dl/{go_latest_trimmed}.{os_str}-amd64.{archive_fmt}");
+    let go_url = format!("https://go.dev/dl/{go_latest_trimmed}.{os_str}-{arch_str}.{archive_fmt}");
 
     Ok(Environment {
         resources: Some(
@@ -80,11 +87,13 @@ pub(crate) fn go() -> Result<Environment> {
                 Resource::File {
                     repo_location: None,
                     file: resources::file::File {
-                        url: InterpolableString::new(go_url),
+                        url: Some(InterpolableString::new(go_url).into()),
                         name: "go".to_string(),
                         sha256: Some(go_latest_sha256.to_string()),
                         sha512: None,
                         proxy: None,
+                        auth: None,
+                        platforms: None,
                         archive: true,
                         executable: false,
                     },
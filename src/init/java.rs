@@ -5,17 +5,20 @@ use jsonpath_rust::JsonPath;
 use reqwest::redirect;
 
 use crate::{
-    Os,
+    Arch, Os, Platform,
     config::{Environment, PathEnv},
+    http,
     interpol::InterpolableString,
     resources::{self, Resource},
+    user_config::read_user_config,
 };
 
-pub(crate) fn java(version: u8) -> Result<Environment> {
-    let client = reqwest::blocking::Client::builder()
+pub(crate) fn java(platform: Platform, version: u8) -> Result<Environment> {
+    let user_conf = read_user_config()?;
+    let client = http::builder(&user_conf, None)?
         .redirect(redirect::Policy::none())
         .build()?;
-    let client_with_redirect = reqwest::blocking::Client::builder()
+    let client_with_redirect = http::builder(&user_conf, None)?
         .redirect(redirect::Policy::default())
         .build()?;
     let version_response = client.get(format!("https://api.adoptium.net/v3/info/release_names?image_type=jdk&jvm_impl=hotspot&release_type=ga&semver=false&version=[{}.0,{}.0)", version, version+1)).send()?.error_for_status()?;
@@ -35,32 +38,21 @@ pub(crate) fn java(version: u8) -> Result<Environment> {
     } else {
         bail!("version json must be an object")
     };
-    let (java_location_url, java_sha_url) = match Os::get() {
-        Os::Linux => (
-            format!(
-                "https://api.adoptium.net/v3/binary/version/{release_name}/linux/x64/jdk/hotspot/normal/eclipse"
-            ),
-            format!(
-                "https://api.adoptium.net/v3/checksum/version/{release_name}/linux/x64/jdk/hotspot/normal/eclipse"
-            ),
-        ),
-        Os::MacOS => (
-            format!(
-                "https://api.adoptium.net/v3/binary/version/{release_name}/mac/x64/jdk/hotspot/normal/eclipse"
-            ),
-            format!(
-                "https://api.adoptium.net/v3/checksum/version/{release_name}/mac/x64/jdk/hotspot/normal/eclipse"
-            ),
-        ),
-        Os::Windows => (
-            format!(
-                "https://api.adoptium.net/v3/binary/version/{release_name}/windows/x64/jdk/hotspot/normal/eclipse"
-            ),
-            format!(
-                "https://api.adoptium.net/v3/checksum/version/{release_name}/windows/x64/jdk/hotspot/normal/eclipse"
-            ),
-        ),
+    let os_str = match platform.os {
+        Os::Linux => "linux",
+        Os::MacOS => "mac",
+        Os::Windows => "windows",
     };
+    let arch_str = match platform.arch {
+        Arch::X86_64 => "x64",
+        Arch::Aarch64 => "aarch64",
+    };
+    let java_location_url = format!(
+        "https://api.adoptium.net/v3/binary/version/{release_name}/{os_str}/{arch_str}/jdk/hotspot/normal/eclipse"
+    );
+    let java_sha_url = format!(
+        "https://api.adoptium.net/v3/checksum/version/{release_name}/{os_str}/{arch_str}/jdk/hotspot/normal/eclipse"
+    );
     let location_response = client.get(java_location_url).send()?.error_for_status()?;
     let sha256_response = client_with_redirect
         .get(java_sha_url)
@@ -85,11 +77,13 @@ pub(crate) fn java(version: u8) -> Result<Environment> {
                 Resource::File {
                     repo_location: None,
                     file: resources::file::File {
-                        url: InterpolableString::new(java_url.to_string()),
+                        url: Some(InterpolableString::new(java_url.to_string()).into()),
                         name: "jdk".to_string(),
                         sha256: Some(java_sha256.to_string()),
                         sha512: None,
                         proxy: None,
+                        auth: None,
+                        platforms: None,
                         archive: true,
                         executable: false,
                     },
@@ -113,7 +107,7 @@ pub(crate) fn java(version: u8) -> Result<Environment> {
 }
 
 pub(crate) fn maven() -> Result<Environment> {
-    let client_with_redirect = reqwest::blocking::Client::builder()
+    let client_with_redirect = http::builder(&read_user_config()?, None)?
         .redirect(redirect::Policy::default())
         .build()?;
     let mvn_latest_req = client_with_redirect
@@ -145,11 +139,13 @@ pub(crate) fn maven() -> Result<Environment> {
                 Resource::File {
                     repo_location: None,
                     file: resources::file::File {
-                        url: InterpolableString::new(mvn_url),
+                        url: Some(InterpolableString::new(mvn_url).into()),
                         name: "mvn".to_string(),
                         sha256: None,
                         sha512: Some(mvn_sha512),
                         proxy: None,
+                        auth: None,
+                        platforms: None,
                         archive: true,
                         executable: false,
                     },
@@ -166,7 +162,7 @@ pub(crate) fn maven() -> Result<Environment> {
 }
 
 pub(crate) fn sbt() -> Result<Environment> {
-    let client_with_redirect = reqwest::blocking::Client::builder()
+    let client_with_redirect = http::builder(&read_user_config()?, None)?
         .redirect(redirect::Policy::default())
         .build()?;
     let sbt_latest_req = client_with_redirect
@@ -206,11 +202,13 @@ pub(crate) fn sbt() -> Result<Environment> {
                 Resource::File {
                     repo_location: None,
                     file: resources::file::File {
-                        url: InterpolableString::new(sbt_url),
+                        url: Some(InterpolableString::new(sbt_url).into()),
                         name: "sbt".to_string(),
                         sha256: Some(sbt_sha256),
                         sha512: None,
                         proxy: None,
+                        auth: None,
+                        platforms: None,
                         archive: true,
                         executable: false,
                     },
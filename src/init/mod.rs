@@ -1,6 +1,6 @@
 use anyhow::{Ok, Result};
 
-use crate::config::Conf;
+use crate::{Platform, config::Conf};
 
 mod golang;
 mod java;
@@ -14,8 +14,12 @@ pub(crate) enum JavaBuildTool {
 }
 
 impl Conf {
-    pub(crate) fn init_java(version: u8, jbt_opt: &Option<JavaBuildTool>) -> Result<Conf> {
-        let java = java::java(version)?;
+    pub(crate) fn init_java(
+        platform: Platform,
+        version: u8,
+        jbt_opt: &Option<JavaBuildTool>,
+    ) -> Result<Conf> {
+        let java = java::java(platform, version)?;
         if let Some(jbt) = jbt_opt {
             let jbt_res = match jbt {
                 JavaBuildTool::Sbt => java::sbt()?,
@@ -24,36 +28,46 @@ impl Conf {
             Ok(Conf {
                 shell: Some(java.merge(jbt_res)?),
                 builder: None,
+                shell_program: None,
+                prompt: None,
             })
         } else {
             Ok(Conf {
                 shell: Some(java),
                 builder: None,
+                shell_program: None,
+                prompt: None,
             })
         }
     }
 
-    pub(crate) fn init_go() -> Result<Conf> {
-        let go = golang::go()?;
+    pub(crate) fn init_go(platform: Platform) -> Result<Conf> {
+        let go = golang::go(platform)?;
         Ok(Conf {
             shell: Some(go),
             builder: None,
+            shell_program: None,
+            prompt: None,
         })
     }
 
-    pub(crate) fn init_node() -> Result<Conf> {
-        let node = node::node()?;
+    pub(crate) fn init_node(platform: Platform) -> Result<Conf> {
+        let node = node::node(platform)?;
         Ok(Conf {
             shell: Some(node),
             builder: None,
+            shell_program: None,
+            prompt: None,
         })
     }
 
-    pub(crate) fn init_python() -> Result<Conf> {
-        let python = python::python()?;
+    pub(crate) fn init_python(platform: Platform) -> Result<Conf> {
+        let python = python::python(platform)?;
         Ok(Conf {
             shell: Some(python),
             builder: None,
+            shell_program: None,
+            prompt: None,
         })
     }
 }
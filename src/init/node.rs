@@ -2,14 +2,16 @@ use anyhow::{Context, Result};
 use reqwest::redirect;
 
 use crate::{
-    Os,
+    Arch, Os, Platform,
     config::{Environment, PathEnv},
+    http,
     interpol::InterpolableString,
     resources::{self, Resource},
+    user_config::read_user_config,
 };
 
-pub(crate) fn node() -> Result<Environment> {
-    let client_with_redirect = reqwest::blocking::Client::builder()
+pub(crate) fn node(platform: Platform) -> Result<Environment> {
+    let client_with_redirect = http::builder(&read_user_config()?, None)?
         .redirect(redirect::Policy::default())
         .build()?;
 
@@ -44,22 +46,27 @@ pub(crate) fn node() -> Result<Environment> {
         .and_then(|v| v.as_str())
         .context("Failed to find the latest LTS Node.js version")?;
 
-    let os_str = match Os::get() {
+    let os_str = match platform.os {
         Os::Linux => "linux",
         Os::MacOS => "darwin",
         Os::Windows => "win",
     };
 
-    let archive_fmt = match Os::get() {
+    let archive_fmt = match platform.os {
         Os::Linux => "tar.xz",
         Os::MacOS => "tar.xz",
         Os::Windows => "zip",
     };
 
+    let arch_str = match platform.arch {
+        Arch::X86_64 => "x64",
+        Arch::Aarch64 => "arm64",
+    };
+
     // Construire les URLs pour le binaire et le checksum
     let node_sha256_url = format!("https://nodejs.org/dist/{node_latest_version}/SHASUMS256.txt");
     let node_url = format!(
-        "https://nodejs.org/dist/{node_latest_version}/node-{node_latest_version}-{os_str}-x64.{archive_fmt}"
+        "https://nodejs.org/dist/{node_latest_version}/node-{node_latest_version}-{os_str}-{arch_str}.{archive_fmt}"
     );
 
     // Récupérer le checksum SHA256
@@ -74,7 +81,7 @@ pub(crate) fn node() -> Result<Environment> {
         .lines()
         .find(|line| {
             line.contains(&format!(
-                "node-{node_latest_version}-{os_str}-x64.{archive_fmt}"
+                "node-{node_latest_version}-{os_str}-{arch_str}.{archive_fmt}"
             ))
         })
         .and_then(|line| line.split_whitespace().next())
@@ -87,11 +94,13 @@ pub(crate) fn node() -> Result<Environment> {
                 Resource::File {
                     repo_location: None,
                     file: resources::file::File {
-                        url: InterpolableString::new(node_url),
+                        url: Some(InterpolableString::new(node_url).into()),
                         name: "node".to_string(),
                         sha256: Some(node_sha256.to_string()),
                         sha512: None,
                         proxy: None,
+                        auth: None,
+                        platforms: None,
                         archive: true,
                         executable: false,
                     },
@@ -104,14 +113,14 @@ pub(crate) fn node() -> Result<Environment> {
             vec![(
                 "NODE_HOME".to_string(),
                 InterpolableString::new(format!(
-                    "${{node}}/node/node-{node_latest_version}-{os_str}-x64"
+                    "${{node}}/node/node-{node_latest_version}-{os_str}-{arch_str}"
                 )),
             )]
             .into_iter()
             .collect(),
         ),
         path: PathEnv(vec![InterpolableString::new(format!(
-            "${{node}}/node/node-{node_latest_version}-{os_str}-x64/bin"
+            "${{node}}/node/node-{node_latest_version}-{os_str}-{arch_str}/bin"
         ))]),
     })
 }
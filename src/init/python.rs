@@ -5,14 +5,16 @@ use reqwest::redirect;
 use sha2::{Digest, Sha256};
 
 use crate::{
-    Os,
+    Arch, Os, Platform,
     config::{Environment, PathEnv},
+    http,
     interpol::InterpolableString,
     resources::{self, Resource},
+    user_config::read_user_config,
 };
 
-pub(crate) fn python() -> Result<Environment> {
-    let client_with_redirect = reqwest::blocking::Client::builder()
+pub(crate) fn python(platform: Platform) -> Result<Environment> {
+    let client_with_redirect = http::builder(&read_user_config()?, None)?
         .redirect(redirect::Policy::default())
         .build()?;
 
@@ -49,12 +51,16 @@ pub(crate) fn python() -> Result<Environment> {
         .context("Failed to find the latest stable Python version")?[7..];
 
     // Construire les URLs pour le binaire
-    let python_url = match Os::get() {
+    let arch_str = match platform.arch {
+        Arch::X86_64 => "amd64",
+        Arch::Aarch64 => "arm64",
+    };
+    let python_url = match platform.os {
         Os::Linux => format!(
             "https://www.python.org/ftp/python/{python_latest_version}/Python-{python_latest_version}.tar.xz"
         ),
         Os::Windows => format!(
-            "https://www.python.org/ftp/python/{python_latest_version}/python-{python_latest_version}-embed-amd64.zip"
+            "https://www.python.org/ftp/python/{python_latest_version}/python-{python_latest_version}-embed-{arch_str}.zip"
         ),
         Os::MacOS => todo!("MacOS support is not implemented yet"),
     };
@@ -75,11 +81,13 @@ pub(crate) fn python() -> Result<Environment> {
                 Resource::File {
                     repo_location: None,
                     file: resources::file::File {
-                        url: InterpolableString::new(python_url),
+                        url: Some(InterpolableString::new(python_url).into()),
                         name: "python".to_string(),
                         sha256: Some(sha256),
                         sha512: None,
                         proxy: None,
+                        auth: None,
+                        platforms: None,
                         archive: true,
                         executable: false,
                     },
@@ -0,0 +1,165 @@
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Component, Path},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+pub const LOCK_FILE: &str = "chenv.lock";
+
+pub type LockedEnvironment = BTreeMap<String, LockedResource>;
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Lock {
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub shell: LockedEnvironment,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub builder: LockedEnvironment,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LockedResource {
+    pub url: String,
+    pub algorithm: String,
+    pub digest: String,
+    #[serde(default)]
+    pub archive: bool,
+    #[serde(default)]
+    pub executable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+}
+
+impl LockedResource {
+    fn diff(&self, other: &LockedResource) -> Vec<String> {
+        let mut changes = Vec::new();
+        if self.url != other.url {
+            changes.push(format!("url {} -> {}", self.url, other.url));
+        }
+        if self.algorithm != other.algorithm || self.digest != other.digest {
+            changes.push(format!(
+                "{} {} -> {} {}",
+                self.algorithm, self.digest, other.algorithm, other.digest
+            ));
+        }
+        if self.archive != other.archive {
+            changes.push(format!("archive {} -> {}", self.archive, other.archive));
+        }
+        if self.executable != other.executable {
+            changes.push(format!(
+                "executable {} -> {}",
+                self.executable, other.executable
+            ));
+        }
+        // le store dépend de la machine, il n'est comparé que s'il est connu des deux côtés
+        if let (Some(s1), Some(s2)) = (&self.store, &other.store)
+            && s1 != s2
+        {
+            changes.push(format!("store {} -> {}", s1, s2));
+        }
+        changes
+    }
+}
+
+pub fn store_path(path: &Path) -> String {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn check(name: &str, expected: Option<&LockedResource>, actual: &LockedResource) -> Result<()> {
+    match expected {
+        None => bail!(
+            "Resource {} is missing from {}, run `chenv lock` to update it",
+            name,
+            LOCK_FILE
+        ),
+        Some(expected) => {
+            let changes = expected.diff(actual);
+            if !changes.is_empty() {
+                bail!(
+                    "Resource {} does not match {} ({}), run `chenv lock` to update it",
+                    name,
+                    LOCK_FILE,
+                    changes.join(", ")
+                )
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn read_lock(config_parent: &Path) -> Result<Option<Lock>> {
+    let path = config_parent.join(LOCK_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let file = std::fs::File::open(&path)?;
+    Ok(Some(
+        serde_yaml::from_reader(file).with_context(|| format!("Cannot read {:?}", path))?,
+    ))
+}
+
+pub fn write_lock(config_parent: &Path, lock: &Lock) -> Result<()> {
+    let yaml = serde_yaml::to_string(lock)?;
+    let mut file = std::fs::File::create(config_parent.join(LOCK_FILE))?;
+    file.write_all(b"# generated by `chenv lock`, do not edit\n")?;
+    file.write_all(yaml.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use anyhow::Result;
+    use tempfile::tempdir;
+    use url::Url;
+
+    use crate::{config::Environment, resources::store::Store, test_utils::tool};
+
+    fn environment(url: &str, sha256: &str) -> Result<Environment> {
+        Ok(serde_yaml::from_str(&format!(
+            r#"
+resources:
+  tool: !File
+    url: {url}
+    name: tool
+    sha256: {sha256}
+    executable: true
+"#
+        ))?)
+    }
+
+    #[test]
+    fn test_lock_drift() -> Result<()> {
+        let tmp = tempdir()?;
+        let tool = tool(tmp.path())?;
+        let url = tool.url.to_string();
+        let sha256 = tool.sha256;
+
+        let store = Store::new(tmp.path(), None);
+        let env = environment(&url, &sha256)?;
+        let lock = env.lock(&store, 1)?;
+        assert_eq!(lock["tool"].url, url);
+        assert_eq!(lock["tool"].algorithm, "sha256");
+        assert_eq!(
+            lock["tool"].store.as_deref(),
+            Some(format!(".chenv/{}", &sha256[..16]).as_str())
+        );
+        env.ensure_resources(&store, Some(&lock), 1)?;
+
+        let moved = tmp.path().join("other.sh");
+        fs::copy(&tool.path, &moved)?;
+        let moved_url = Url::from_file_path(&moved).unwrap().to_string();
+        let drifted = environment(&moved_url, &sha256)?;
+        let err = drifted.ensure_resources(&store, Some(&lock), 1).err();
+        assert!(err.unwrap().to_string().contains("does not match"));
+        assert!(drifted.ensure_resources(&store, None, 1).is_ok());
+        Ok(())
+    }
+}
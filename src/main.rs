@@ -1,18 +1,34 @@
 use std::{
-    env::{self, join_paths, split_paths},
+    collections::BTreeMap,
+    env::{self, current_dir, join_paths, split_paths},
     io::Write,
-    path::{Path, PathBuf},
-    process::Command,
+    path::{Path, PathBuf, absolute},
+    process::{Command, ExitStatus},
 };
 
 use anyhow::{Context, Result, bail};
 use config::Conf;
 use init::JavaBuildTool;
+use resources::store::Store;
+use shell::Shell;
+use user_config::read_user_config;
 mod cli;
 mod config;
+mod export;
+mod fetch;
+mod gc;
+mod hook;
+mod http;
 mod init;
 mod interpol;
+mod lock;
+mod progress;
 mod resources;
+mod shell;
+#[cfg(test)]
+mod test_utils;
+mod user_config;
+mod verify;
 #[derive(Debug, Clone, Copy)]
 enum Os {
     Linux,
@@ -30,11 +46,11 @@ impl Os {
             _ => panic!("Unsupported OS {}", os),
         }
     }
-    fn get_shell(&self) -> &'static str {
+    fn get_shell(&self) -> Shell {
         match self {
-            Os::Linux => "bash",
-            Os::MacOS => "bash",
-            Os::Windows => "cmd",
+            Os::Linux => Shell::Bash,
+            Os::MacOS => Shell::Bash,
+            Os::Windows => Shell::Cmd,
         }
     }
     fn get_code(&self) -> &'static str {
@@ -53,6 +69,43 @@ impl Os {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+enum Arch {
+    X86_64,
+    Aarch64,
+}
+
+impl Arch {
+    fn get() -> Result<Self> {
+        let arch = std::env::consts::ARCH;
+        match arch {
+            "x86_64" => Ok(Arch::X86_64),
+            "aarch64" => Ok(Arch::Aarch64),
+            _ => bail!("Unsupported architecture {}", arch),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Platform {
+    os: Os,
+    arch: Arch,
+}
+
+impl Platform {
+    fn get() -> Result<Self> {
+        Ok(Platform {
+            os: Os::get(),
+            arch: Arch::get()?,
+        })
+    }
+    // clé de la machine courante (`linux-x86_64`, `macos-aarch64`...), y compris pour une
+    // architecture que `chenv init` ne connaît pas
+    fn key() -> String {
+        format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+    }
+}
+
 fn main() -> Result<()> {
     env_logger::init();
     let os = Os::get();
@@ -61,8 +114,8 @@ fn main() -> Result<()> {
         cli::Command::Code { path } => {
             let mut cmd = Command::new(os.get_code());
             cmd.arg("-n").arg("--wait").arg(path);
-            let conf = config::read_config_in_repo(path)?;
-            set_shell(&mut cmd, &conf, path)?;
+            let (conf, config_parent) = config::read_config_in_repo(&absolute(path)?)?;
+            set_shell(&mut cmd, &conf, &config_parent, Options::get(&args)?)?;
             cmd.status().expect("shell failed to start");
         }
         cli::Command::Init {
@@ -81,7 +134,7 @@ fn main() -> Result<()> {
                 (false, true) => None,
                 (false, false) => Some(JavaBuildTool::Maven),
             };
-            let conf = Conf::init_java(*version, &jbt_opt)?;
+            let conf = Conf::init_java(Platform::get()?, *version, &jbt_opt)?;
             let yaml = serde_yaml::to_string(&conf)?;
             let mut file = std::fs::File::create("chenv.yaml")?;
             file.write_all(yaml.as_bytes())?;
@@ -89,7 +142,7 @@ fn main() -> Result<()> {
         cli::Command::Init {
             lang: cli::Lang::Go,
         } => {
-            let conf = Conf::init_go()?;
+            let conf = Conf::init_go(Platform::get()?)?;
             let yaml = serde_yaml::to_string(&conf)?;
             let mut file = std::fs::File::create("chenv.yaml")?;
             file.write_all(yaml.as_bytes())?;
@@ -97,7 +150,7 @@ fn main() -> Result<()> {
         cli::Command::Init {
             lang: cli::Lang::Node,
         } => {
-            let conf = Conf::init_node()?;
+            let conf = Conf::init_node(Platform::get()?)?;
             let yaml = serde_yaml::to_string(&conf)?;
             let mut file = std::fs::File::create("chenv.yaml")?;
             file.write_all(yaml.as_bytes())?;
@@ -105,34 +158,158 @@ fn main() -> Result<()> {
         cli::Command::Init {
             lang: cli::Lang::Python,
         } => {
-            let conf = Conf::init_python()?;
+            let conf = Conf::init_python(Platform::get()?)?;
             let yaml = serde_yaml::to_string(&conf)?;
             let mut file = std::fs::File::create("chenv.yaml")?;
             file.write_all(yaml.as_bytes())?;
         }
-        cli::Command::Shell { path } => {
-            let conf = match path {
-                Some(path) => config::read_config(path)?,
-                None => config::read_config_in_repo(&args.get_repository_path()?)?,
-            };
-            let mut cmd = Command::new(os.get_shell());
-            set_shell(&mut cmd, &conf, &args.get_repository_path()?)?;
+        cli::Command::Shell { path, shell } => {
+            let (conf, config_parent) = load_config(path)?;
+            let shell = shell
+                .or(conf.shell_program)
+                .unwrap_or_else(|| Shell::detect(os));
+            let vars = get_vars(&conf, &config_parent, Options::get(&args)?)?;
+            let prompt = conf.prompt.as_deref().unwrap_or(shell::DEFAULT_PROMPT);
+            let (mut cmd, _init_dir) = shell.command(&vars, prompt)?;
             cmd.status().expect("shell failed to start");
         }
+        cli::Command::Exec { config: path, cmd } => {
+            let (conf, config_parent) = load_config(path)?;
+            let (program, program_args) = cmd.split_first().context("Missing command")?;
+            let mut command = Command::new(program);
+            command.args(program_args);
+            set_shell(&mut command, &conf, &config_parent, Options::get(&args)?)?;
+            let status = command
+                .status()
+                .with_context(|| format!("Cannot run {}", program))?;
+            std::process::exit(exit_code(status));
+        }
+        cli::Command::Env {
+            config: path,
+            format,
+        } => {
+            let (conf, config_parent) = load_config(path)?;
+            let vars = get_vars(&conf, &config_parent, Options::get(&args)?)?;
+            print!("{}", export::export(*format, &vars)?);
+        }
+        cli::Command::Hook { shell } => {
+            print!("{}", hook::hook(*shell)?);
+        }
+        cli::Command::HookEnv { shell } => {
+            print!("{}", hook::hook_env(*shell, Options::get(&args)?)?);
+        }
+        cli::Command::Gc {
+            dry_run,
+            older_than,
+        } => {
+            gc::gc(&read_user_config()?, *older_than, *dry_run)?;
+        }
+        cli::Command::Verify { config: path, fix } => {
+            let (conf, config_parent) = load_config(path)?;
+            let options = Options::get(&args)?;
+            let store = options.store(&config_parent)?;
+            let lock = lock::read_lock(&config_parent)?;
+            verify::verify(&conf, &store, lock.as_ref(), *fix, options.jobs)?;
+        }
+        cli::Command::Lock { path } => {
+            let (conf, config_parent) = load_config(path)?;
+            let options = Options::get(&args)?;
+            let store = options.store(&config_parent)?;
+            let lock = lock::Lock {
+                shell: match &conf.shell {
+                    Some(shell) => shell.lock(&store, options.jobs)?,
+                    None => Default::default(),
+                },
+                builder: match &conf.builder {
+                    Some(builder) => builder.env.lock(&store, options.jobs)?,
+                    None => Default::default(),
+                },
+            };
+            lock::write_lock(&config_parent, &lock)?;
+        }
+        cli::Command::Fetch { config: path } => {
+            let (conf, config_parent) = load_config(path)?;
+            let options = Options::get(&args)?;
+            let store = options.store(&config_parent)?;
+            let lock = lock::read_lock(&config_parent)?;
+            fetch::fetch(&conf, &store, lock.as_ref(), options.jobs)?;
+        }
     }
     Ok(())
 }
 
-fn set_shell(cmd: &mut Command, conf: &config::Conf, config_parent: &Path) -> Result<()> {
+fn load_config(path: &Option<PathBuf>) -> Result<(Conf, PathBuf)> {
+    match path {
+        Some(path) => {
+            let config_parent = absolute(path)?
+                .parent()
+                .with_context(|| format!("Configuration cannot be found as {:?}", path))?
+                .to_owned();
+            Ok((config::read_config(path)?, config_parent))
+        }
+        None => config::read_config_in_repo(&current_dir()?),
+    }
+}
+
+// réglages de l'installation des ressources, communs à toutes les commandes
+#[derive(Debug, Clone, Copy)]
+struct Options {
+    jobs: usize,
+    offline: bool,
+}
+
+impl Options {
+    fn get(args: &cli::Cli) -> Result<Self> {
+        // option --jobs, sinon $CHENV_JOBS ou la configuration utilisateur
+        let jobs = match args.jobs {
+            Some(jobs) => jobs.max(1),
+            None => read_user_config()?.jobs(),
+        };
+        let offline = args.offline
+            || env::var("CHENV_OFFLINE").is_ok_and(|offline| offline == "1" || offline == "true");
+        Ok(Self { jobs, offline })
+    }
+
+    fn store(&self, config_parent: &Path) -> Result<Store> {
+        let user_conf = read_user_config()?;
+        Ok(Store::new(config_parent, user_conf.global_store())
+            .with_offline(self.offline)
+            .with_user_conf(user_conf))
+    }
+}
+
+fn exit_code(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    1
+}
+
+fn get_vars(
+    conf: &config::Conf,
+    config_parent: &Path,
+    options: Options,
+) -> Result<BTreeMap<String, String>> {
+    let mut vars = BTreeMap::new();
     if let Some(shell) = &conf.shell {
-        let interpolation_env = shell.ensure_resources(config_parent)?;
+        let lock = lock::read_lock(config_parent)?;
+        let store = options.store(config_parent)?;
+        let interpolation_env =
+            shell.ensure_resources(&store, lock.as_ref().map(|l| &l.shell), options.jobs)?;
         for (k, v) in shell.get_env(&interpolation_env)? {
-            cmd.env(k, v);
+            vars.insert(k.clone(), v);
         }
         let mut paths = shell
             .get_path(&interpolation_env)?
             .into_iter()
-            .map(|s| PathBuf::from(s))
+            .map(PathBuf::from)
             .collect::<Vec<_>>();
         let mut init_paths = match env::var_os(Os::get().get_path()) {
             Some(path) => split_paths(&path).collect(),
@@ -140,7 +317,22 @@ fn set_shell(cmd: &mut Command, conf: &config::Conf, config_parent: &Path) -> Re
         };
         paths.append(&mut init_paths);
         let path_new = join_paths(paths)?;
-        cmd.env(Os::get().get_path(), path_new);
+        vars.insert(
+            Os::get().get_path().to_string(),
+            path_new.to_string_lossy().to_string(),
+        );
+    }
+    Ok(vars)
+}
+
+fn set_shell(
+    cmd: &mut Command,
+    conf: &config::Conf,
+    config_parent: &Path,
+    options: Options,
+) -> Result<()> {
+    for (k, v) in get_vars(conf, config_parent, options)? {
+        cmd.env(k, v);
     }
     Ok(())
 }
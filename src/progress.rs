@@ -0,0 +1,152 @@
+use std::{
+    io::{self, IsTerminal, Read},
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::gc::human_size;
+
+// intervalle entre deux lignes de progression quand stderr n'est pas un terminal
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+// barres de tous les téléchargements en cours, éventuellement simultanés
+fn bars() -> &'static MultiProgress {
+    static BARS: OnceLock<MultiProgress> = OnceLock::new();
+    BARS.get_or_init(|| MultiProgress::with_draw_target(ProgressDrawTarget::stderr()))
+}
+
+// affiche un message sur stderr sans casser les barres en cours
+pub fn message(msg: &str) {
+    bars().suspend(|| eprintln!("{}", msg));
+}
+
+enum Display {
+    Bar(ProgressBar),
+    Log { start: Instant, last: Instant },
+}
+
+pub struct Progress {
+    name: String,
+    total: Option<u64>,
+    done: u64,
+    display: Display,
+}
+
+impl Progress {
+    pub fn new(name: &str, total: Option<u64>) -> Self {
+        let display = if io::stderr().is_terminal() {
+            let (bar, template) = match total {
+                Some(total) => (
+                    ProgressBar::new(total),
+                    "{msg:24!} [{bar:30}] {bytes}/{total_bytes} {bytes_per_sec} ETA {eta}",
+                ),
+                None => (
+                    ProgressBar::no_length(),
+                    "{msg:24!} {spinner} {bytes} {bytes_per_sec}",
+                ),
+            };
+            bar.set_style(
+                ProgressStyle::with_template(template)
+                    .expect("Progress template should be valid")
+                    .progress_chars("=> "),
+            );
+            bar.set_message(name.to_string());
+            Display::Bar(bars().add(bar))
+        } else {
+            let now = Instant::now();
+            Display::Log {
+                start: now,
+                last: now,
+            }
+        };
+        Self {
+            name: name.to_string(),
+            total,
+            done: 0,
+            display,
+        }
+    }
+
+    pub fn inc(&mut self, n: u64) {
+        self.done += n;
+        let log_since = match &mut self.display {
+            Display::Bar(bar) => {
+                bar.inc(n);
+                None
+            }
+            Display::Log { start, last } if last.elapsed() >= LOG_INTERVAL => {
+                *last = Instant::now();
+                Some(*start)
+            }
+            Display::Log { .. } => None,
+        };
+        if let Some(start) = log_since {
+            eprintln!("Progress: {}", self.status(start));
+        }
+    }
+
+    fn status(&self, start: Instant) -> String {
+        let rate = self.done as f64 / start.elapsed().as_secs_f64().max(0.001);
+        match self.total {
+            Some(total) if total > 0 => format!(
+                "{} {} / {} ({}%) at {}/s",
+                self.name,
+                human_size(self.done),
+                human_size(total),
+                self.done * 100 / total,
+                human_size(rate as u64)
+            ),
+            _ => format!(
+                "{} {} at {}/s",
+                self.name,
+                human_size(self.done),
+                human_size(rate as u64)
+            ),
+        }
+    }
+
+    pub fn finish(self) {
+        if let Display::Log { start, .. } = &self.display
+            && start.elapsed() >= LOG_INTERVAL
+        {
+            eprintln!("Progress: {}", self.status(*start));
+        }
+    }
+
+    pub fn wrap<R: Read>(self, inner: R) -> ProgressReader<R> {
+        ProgressReader {
+            inner,
+            progress: self,
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if let Display::Bar(bar) = &self.display {
+            bar.finish_and_clear();
+            bars().remove(bar);
+        }
+    }
+}
+
+pub struct ProgressReader<R> {
+    inner: R,
+    progress: Progress,
+}
+
+impl<R> ProgressReader<R> {
+    pub fn finish(self) {
+        self.progress.finish();
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.progress.inc(n as u64);
+        Ok(n)
+    }
+}
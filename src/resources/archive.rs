@@ -0,0 +1,174 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    interpol::{Env, InterpolableString},
+    lock::LockedResource,
+};
+
+use super::{
+    Substrate,
+    fetch::{Sha, fetch},
+    store::{self, Store},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Archive {
+    pub url: InterpolableString,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha512: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+}
+
+// les archives extraites ont leur propre espace de clés dans le store : une ressource File de
+// même hash (par exemple avec `archive: true`) n'a pas la même arborescence dans son entrée
+fn key(sha: &Sha) -> String {
+    format!("{:x}", Sha256::digest(format!("archive:{}", sha.digest())))
+}
+
+impl Archive {
+    pub fn entry(&self, store: &Store, repo_location: Option<&Path>) -> Result<PathBuf> {
+        let sha = Sha::new(&self.sha256, &self.sha512)?;
+        Ok(store.entry(repo_location, &key(&sha)))
+    }
+
+    pub fn ensure_resources(
+        &self,
+        env: &Env,
+        store: &Store,
+        repo_location: Option<&Path>,
+    ) -> Result<(Substrate, bool)> {
+        let sha = Sha::new(&self.sha256, &self.sha512)?;
+        let output_dir = self.entry(store, repo_location)?;
+        let substrate = Substrate::new(
+            std::path::absolute(&output_dir)?
+                .to_string_lossy()
+                .to_string(),
+        );
+        if output_dir.exists() {
+            return Ok((substrate, false));
+        }
+        let _lock = store::lock_entry(&output_dir)?;
+        // installé par un autre processus pendant l'attente du verrou
+        if output_dir.exists() {
+            return Ok((substrate, false));
+        }
+        let url_str = self.url.interpolate(env)?;
+        let fetched = fetch(
+            &url_str,
+            &self.proxy,
+            None,
+            env,
+            store,
+            &store::partial_download(&output_dir)?,
+            &sha,
+        )?;
+        let path = fetched.path();
+        let staging = store::staging(&output_dir)?;
+        let staged = staging.path().join("entry");
+        mkar::unarchive(path, &staged)
+            .with_context(|| format!("Cannot unarchive {} into {:?}", url_str, staged))?;
+        store::commit(&staged, &output_dir)?;
+        Ok((substrate, true))
+    }
+
+    pub fn lock(
+        &self,
+        env: &Env,
+        store: &Store,
+        repo_location: Option<&Path>,
+    ) -> Result<LockedResource> {
+        let sha = Sha::new(&self.sha256, &self.sha512)?;
+        Ok(LockedResource {
+            url: self.url.interpolate(env)?,
+            algorithm: sha.algorithm().to_string(),
+            digest: sha.digest().to_string(),
+            archive: true,
+            executable: false,
+            store: store.lock_path(repo_location, &key(&sha)),
+        })
+    }
+
+    pub fn get_dependances(&self) -> Vec<&str> {
+        self.url.get_variables()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, process::Command};
+
+    use anyhow::{Result, bail};
+    use sha2::{Digest, Sha256};
+    use tempfile::tempdir;
+    use url::Url;
+
+    use super::Archive;
+    use crate::{
+        interpol::{Env, InterpolableString},
+        resources::store::Store,
+    };
+
+    #[test]
+    fn test_archive_extracted_into_entry() -> Result<()> {
+        let tmp = tempdir()?;
+        let content = tmp.path().join("content");
+        fs::create_dir_all(content.join("bin"))?;
+        fs::write(content.join("bin").join("tool"), "echo tool")?;
+        let tarball = tmp.path().join("tool.tar.gz");
+        let status = Command::new("tar")
+            .arg("-czf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(&content)
+            .arg("bin")
+            .status()?;
+        if !status.success() {
+            bail!("Cannot create {:?}", tarball);
+        }
+        let archive = Archive {
+            url: InterpolableString::new(Url::from_file_path(&tarball).unwrap().to_string()),
+            sha256: Some(format!("{:x}", Sha256::digest(fs::read(&tarball)?))),
+            sha512: None,
+            proxy: None,
+        };
+        let store = Store::new(tmp.path(), None);
+        let (substrate, installed) = archive.ensure_resources(&Env::new(), &store, None)?;
+        assert!(installed);
+        assert_eq!(
+            Path::new(&substrate.to_string()),
+            archive.entry(&store, None)?
+        );
+        assert_eq!(
+            fs::read_to_string(Path::new(&substrate.to_string()).join("bin").join("tool"))?,
+            "echo tool"
+        );
+        // second call reuses the extracted tree
+        assert!(!archive.ensure_resources(&Env::new(), &store, None)?.1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive_entry_not_shared_with_file() -> Result<()> {
+        let digest = "bb16739fcad964c197752200ff89d89aad7b118cb1de5725dc53fe924c40e3f7";
+        let archive = Archive {
+            url: InterpolableString::new("https://example.com/jdk.tar.gz".to_string()),
+            sha256: Some(digest.to_string()),
+            sha512: None,
+            proxy: None,
+        };
+        for store in [
+            Store::new(Path::new("/repo"), None),
+            Store::new(Path::new("/repo"), Some("/cache/store".into())),
+        ] {
+            assert_ne!(archive.entry(&store, None)?, store.entry(None, digest));
+        }
+        Ok(())
+    }
+}
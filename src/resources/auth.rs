@@ -0,0 +1,236 @@
+use std::{collections::BTreeMap, env, fs, path::PathBuf};
+
+use anyhow::{Context, Result};
+use reqwest::{
+    blocking::RequestBuilder,
+    header::{HeaderName, HeaderValue},
+    redirect::Policy,
+};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    interpol::{Env, InterpolableString},
+    user_config::UserConf,
+};
+
+// identifiants d'une ressource ou d'un hôte ; les valeurs sont interpolées au moment du
+// téléchargement et ne sont jamais écrites dans les logs ni dans chenv.lock
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Auth {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bearer: Option<InterpolableString>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub basic: Option<Basic>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, InterpolableString>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Basic {
+    pub user: InterpolableString,
+    pub password: InterpolableString,
+}
+
+// identifiants résolus, volontairement sans Debug
+#[derive(Default)]
+pub(super) struct Credentials {
+    bearer: Option<String>,
+    basic: Option<(String, String)>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl Credentials {
+    // les identifiants de la ressource sont prioritaires, sauf si une règle de réécriture envoie
+    // le téléchargement vers un autre hôte ; viennent ensuite ceux de l'hôte dans la
+    // configuration utilisateur, puis ~/.netrc
+    pub(super) fn resolve(
+        original: &Url,
+        url: &Url,
+        resource: Option<&Auth>,
+        user_conf: &UserConf,
+        env: &Env,
+    ) -> Result<Self> {
+        let Some(host) = url.host_str() else {
+            return Ok(Self::default());
+        };
+        if let Some(auth) = resource
+            && original.host_str() == Some(host)
+        {
+            return Self::from_auth(auth, env);
+        }
+        if let Some(auth) = user_conf.auth.get(host) {
+            return Self::from_auth(auth, env);
+        }
+        if let Some(content) = netrc_path().and_then(|path| fs::read_to_string(path).ok())
+            && let Some(basic) = netrc_lookup(&content, host)
+        {
+            return Ok(Self {
+                basic: Some(basic),
+                ..Default::default()
+            });
+        }
+        Ok(Self::default())
+    }
+
+    fn from_auth(auth: &Auth, env: &Env) -> Result<Self> {
+        let mut headers = Vec::new();
+        for (name, value) in &auth.headers {
+            let header = HeaderName::try_from(name.as_str())
+                .with_context(|| format!("Invalid header name {}", name))?;
+            let mut value = HeaderValue::try_from(value.interpolate(env)?)
+                .with_context(|| format!("Invalid value for header {}", name))?;
+            value.set_sensitive(true);
+            headers.push((header, value));
+        }
+        Ok(Self {
+            bearer: auth
+                .bearer
+                .as_ref()
+                .map(|bearer| bearer.interpolate(env))
+                .transpose()?,
+            basic: match &auth.basic {
+                Some(basic) => Some((
+                    basic.user.interpolate(env)?,
+                    basic.password.interpolate(env)?,
+                )),
+                None => None,
+            },
+            headers,
+        })
+    }
+
+    // reqwest ne retire que les en-têtes d'authentification standards lors d'une redirection vers
+    // un autre hôte : avec des en-têtes personnalisés, les redirections sont suivies par l'appelant
+    pub(super) fn redirect_policy(&self) -> Policy {
+        if self.headers.is_empty() {
+            Policy::default()
+        } else {
+            Policy::none()
+        }
+    }
+
+    pub(super) fn apply(&self, mut request: RequestBuilder) -> RequestBuilder {
+        if let Some(bearer) = &self.bearer {
+            request = request.bearer_auth(bearer);
+        }
+        if let Some((user, password)) = &self.basic {
+            request = request.basic_auth(user, Some(password));
+        }
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        request
+    }
+}
+
+fn netrc_path() -> Option<PathBuf> {
+    match env::var_os("NETRC") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => dirs::home_dir().map(|home| home.join(".netrc")),
+    }
+}
+
+// cherche le login et le mot de passe de host, ou de l'entrée `default`
+fn netrc_lookup(content: &str, host: &str) -> Option<(String, String)> {
+    let mut tokens = content.split_whitespace();
+    let mut found = None;
+    let mut default = None;
+    // entrée en cours : (est-ce host ?, est-ce default ?, login, password)
+    let mut current: Option<(bool, bool, Option<&str>, Option<&str>)> = None;
+    let mut finish = |entry: Option<(bool, bool, Option<&str>, Option<&str>)>| {
+        if let Some((matches, is_default, Some(login), Some(password))) = entry {
+            let credentials = Some((login.to_string(), password.to_string()));
+            if matches && found.is_none() {
+                found = credentials;
+            } else if is_default && default.is_none() {
+                default = credentials;
+            }
+        }
+    };
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" => {
+                finish(current.take());
+                current = Some((tokens.next() == Some(host), false, None, None));
+            }
+            "default" => {
+                finish(current.take());
+                current = Some((false, true, None, None));
+            }
+            "login" => {
+                if let Some(entry) = current.as_mut() {
+                    entry.2 = tokens.next();
+                }
+            }
+            "password" => {
+                if let Some(entry) = current.as_mut() {
+                    entry.3 = tokens.next();
+                }
+            }
+            // les macros s'arrêtent à la première ligne vide, que split_whitespace ne voit pas
+            "macdef" => break,
+            _ => {}
+        }
+    }
+    finish(current.take());
+    found.or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use url::Url;
+
+    use super::{Auth, Credentials, netrc_lookup};
+    use crate::{
+        interpol::{Env, InterpolableString},
+        user_config::UserConf,
+    };
+
+    #[test]
+    fn test_netrc_lookup() {
+        let netrc = "machine github.com login octocat password secret\n\
+                     machine gitlab.com\n  login ci\n  password token\n\
+                     default login anonymous password guest\n";
+        assert_eq!(
+            netrc_lookup(netrc, "gitlab.com"),
+            Some(("ci".to_string(), "token".to_string()))
+        );
+        assert_eq!(
+            netrc_lookup(netrc, "example.com"),
+            Some(("anonymous".to_string(), "guest".to_string()))
+        );
+        assert_eq!(
+            netrc_lookup("machine github.com login a", "github.com"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_resource_auth_not_sent_to_rewritten_host() -> Result<()> {
+        let resource = Auth {
+            bearer: Some(InterpolableString::new("resource".to_string())),
+            ..Default::default()
+        };
+        let mut user_conf = UserConf::default();
+        user_conf.auth.insert(
+            "artifactory.corp".to_string(),
+            Auth {
+                bearer: Some(InterpolableString::new("host".to_string())),
+                ..Default::default()
+            },
+        );
+        let original = Url::parse("https://github.com/k9s.tar.gz")?;
+        let rewritten = Url::parse("https://artifactory.corp/github/k9s.tar.gz")?;
+        let env = Env::new();
+
+        let credentials =
+            Credentials::resolve(&original, &original, Some(&resource), &user_conf, &env)?;
+        assert_eq!(credentials.bearer.as_deref(), Some("resource"));
+        let credentials =
+            Credentials::resolve(&original, &rewritten, Some(&resource), &user_conf, &env)?;
+        assert_eq!(credentials.bearer.as_deref(), Some("host"));
+        Ok(())
+    }
+}
@@ -0,0 +1,536 @@
+use std::{
+    fs,
+    io::{self, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow, bail};
+use reqwest::{
+    StatusCode,
+    blocking::{Client, Response},
+    header::{CONTENT_RANGE, LOCATION, RANGE},
+};
+use sha2::{Digest, Sha256, Sha512};
+use url::Url;
+
+use crate::{
+    http,
+    interpol::Env,
+    progress::{self, Progress},
+};
+
+use super::{
+    OfflineError,
+    auth::{Auth, Credentials},
+    store::Store,
+};
+
+// le hash nomme l'entrée dans le store : il ne doit pas pouvoir désigner un autre chemin
+fn hex_digest<'a>(digest: &'a String, algorithm: &str, len: usize) -> Result<&'a String> {
+    if digest.len() != len
+        || !digest
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    {
+        bail!(
+            "{} {} must be {} lowercase hexadecimal characters",
+            algorithm,
+            digest,
+            len
+        )
+    }
+    Ok(digest)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Sha<'a> {
+    Sha256(&'a String),
+    Sha512(&'a String),
+}
+
+impl<'a> Sha<'a> {
+    pub(super) fn new(sha256: &'a Option<String>, sha512: &'a Option<String>) -> Result<Self> {
+        match (sha256, sha512) {
+            (None, None) => bail!("Need sha256 or sha512"),
+            (None, Some(s)) => Ok(Sha::Sha512(hex_digest(s, "sha512", 128)?)),
+            (Some(s), None) => Ok(Sha::Sha256(hex_digest(s, "sha256", 64)?)),
+            (Some(_), Some(_)) => bail!("Cannot have both sha256 and sha512"),
+        }
+    }
+
+    pub(super) fn compare(&self, path: &Path) -> Result<bool> {
+        match self {
+            Sha::Sha256(s) => {
+                let mut hasher = Sha256::new();
+                let file = std::fs::File::open(path)?;
+                let mut reader = BufReader::new(file);
+                io::copy(&mut reader, &mut hasher)?;
+                let digest = hasher.finalize();
+                let digest = format!("{:x}", digest);
+                Ok(&digest == *s)
+            }
+            Sha::Sha512(s) => {
+                let mut hasher = Sha512::new();
+                let file = std::fs::File::open(path)?;
+                let mut reader = BufReader::new(file);
+                io::copy(&mut reader, &mut hasher)?;
+                let digest = hasher.finalize();
+                let digest = format!("{:x}", digest);
+                Ok(&digest == *s)
+            }
+        }
+    }
+
+    pub(super) fn algorithm(&self) -> &'static str {
+        match self {
+            Sha::Sha256(_) => "sha256",
+            Sha::Sha512(_) => "sha512",
+        }
+    }
+
+    pub(super) fn digest(&self) -> &'a str {
+        match self {
+            Sha::Sha256(s) => s,
+            Sha::Sha512(s) => s,
+        }
+    }
+}
+
+pub(super) enum Fetched {
+    // fichier local (url `file://`), qui ne doit pas être modifié
+    Local(PathBuf),
+    // fichier téléchargé à côté de l'entrée, supprimé s'il n'a pas été déplacé
+    Downloaded(PathBuf),
+}
+
+impl Fetched {
+    pub(super) fn path(&self) -> &Path {
+        match self {
+            Fetched::Local(path) => path,
+            Fetched::Downloaded(path) => path,
+        }
+    }
+}
+
+impl Drop for Fetched {
+    fn drop(&mut self) {
+        if let Fetched::Downloaded(path) = self {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+const MAX_ATTEMPTS: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_REDIRECTS: usize = 10;
+
+enum Failure {
+    // coupure réseau ou erreur du serveur : on peut réessayer
+    Transient(anyhow::Error),
+    Fatal(anyhow::Error),
+}
+
+// récupère url et vérifie son hash ; un téléchargement interrompu est conservé dans partial
+// et repris au prochain essai si le serveur accepte les requêtes Range
+pub(super) fn fetch(
+    url_str: &str,
+    proxy: &Option<String>,
+    auth: Option<&Auth>,
+    env: &Env,
+    store: &Store,
+    partial: &Path,
+    sha: &Sha,
+) -> Result<Fetched> {
+    let user_conf = store.user_conf();
+    let original = url_str.parse::<Url>()?;
+    // les règles de réécriture ne changent que l'emplacement : le hash reste vérifié
+    let url = user_conf.rewrite(url_str).parse::<Url>()?;
+    if store.offline() && url.scheme() != "file" {
+        return Err(OfflineError {
+            url: url.to_string(),
+        }
+        .into());
+    }
+    progress::message(&format!("Get: {}", url));
+    let fetched = if url.scheme() == "file" {
+        let path = url
+            .to_file_path()
+            .map_err(|()| anyhow!("Url {} is not a file", url))?;
+        Fetched::Local(path)
+    } else if url.scheme() == "http" || url.scheme() == "https" {
+        if let Some(parent) = partial.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let credentials = Credentials::resolve(&original, &url, auth, user_conf, env)?;
+        let client = http::builder(user_conf, proxy.as_deref())?
+            .redirect(credentials.redirect_policy())
+            .build()?;
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            match download(&client, &url, &credentials, partial) {
+                Ok(()) => break,
+                Err(Failure::Transient(e)) if attempt < MAX_ATTEMPTS => {
+                    progress::message(&format!(
+                        "Retrying {} in {}s: {:#}",
+                        url,
+                        backoff.as_secs(),
+                        e
+                    ));
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(Failure::Transient(e) | Failure::Fatal(e)) => {
+                    return Err(e.context(format!("Cannot download {}", url)));
+                }
+            }
+        }
+        Fetched::Downloaded(partial.to_owned())
+    } else {
+        bail!("Unsupported scheme {}", url.scheme());
+    };
+    let path = fetched.path();
+    if !path.exists() {
+        bail!("File {:?} is missing", path)
+    }
+    // un fichier incorrect ne doit pas être repris : il est supprimé avec fetched
+    if !sha.compare(path)? {
+        bail!("URL {} must have hash equal to {:?}", url, sha)
+    }
+    Ok(fetched)
+}
+
+fn download(
+    client: &Client,
+    url: &Url,
+    credentials: &Credentials,
+    partial: &Path,
+) -> Result<(), Failure> {
+    let offset = fs::metadata(partial).map(|m| m.len()).unwrap_or(0);
+    let mut target = url.clone();
+    let mut sent = Some(credentials);
+    let mut redirects = 0;
+    let response = loop {
+        let mut request = client.get(target.clone());
+        if let Some(credentials) = sent {
+            request = credentials.apply(request);
+        }
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", offset));
+        }
+        let response = request.send().map_err(|e| Failure::Transient(e.into()))?;
+        // redirection laissée par la politique des identifiants : ils ne suivent pas vers un
+        // autre hôte
+        let Some(next) = redirect_location(&response, &target) else {
+            break response;
+        };
+        redirects += 1;
+        if redirects > MAX_REDIRECTS {
+            return Err(Failure::Fatal(anyhow!("Too many redirects from {}", url)));
+        }
+        if next.host_str() != target.host_str()
+            || next.port_or_known_default() != target.port_or_known_default()
+        {
+            sent = None;
+        }
+        target = next;
+    };
+    let status = response.status();
+    let resumed = match status {
+        StatusCode::PARTIAL_CONTENT if offset > 0 => {
+            // une autre plage que celle demandée ne peut pas compléter le fichier partiel
+            if range_start(&response) != Some(offset) {
+                drop(response);
+                fs::remove_file(partial).map_err(|e| Failure::Fatal(e.into()))?;
+                return download(client, url, credentials, partial);
+            }
+            true
+        }
+        // le fichier partiel est déjà complet, le hash le confirmera
+        StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => return Ok(()),
+        status if status.is_success() => false,
+        status if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS => {
+            return Err(Failure::Transient(anyhow!(
+                "{} returned {}",
+                target,
+                status
+            )));
+        }
+        status => return Err(Failure::Fatal(anyhow!("{} returned {}", target, status))),
+    };
+    let file = if resumed {
+        fs::File::options().append(true).open(partial)
+    } else {
+        fs::File::create(partial)
+    };
+    let mut file = file.map_err(|e| Failure::Fatal(e.into()))?;
+    let start = if resumed { offset } else { 0 };
+    // nom affiché : dernier segment de l'url
+    let label = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|segment| !segment.is_empty())
+        .unwrap_or(url.as_str());
+    let mut progress = Progress::new(label, response.content_length().map(|l| l + start));
+    progress.inc(start);
+    let mut body_reader = progress.wrap(BufReader::new(response));
+    // seule la lecture de la réponse peut être réessayée : une erreur d'écriture locale (disque
+    // plein...) se reproduirait
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let n = body_reader
+            .read(&mut buffer)
+            .map_err(|e| Failure::Transient(e.into()))?;
+        if n == 0 {
+            break;
+        }
+        file.write_all(&buffer[..n])
+            .map_err(|e| Failure::Fatal(e.into()))?;
+    }
+    file.flush().map_err(|e| Failure::Fatal(e.into()))?;
+    body_reader.finish();
+    Ok(())
+}
+
+fn redirect_location(response: &Response, url: &Url) -> Option<Url> {
+    if !response.status().is_redirection() {
+        return None;
+    }
+    let location = response.headers().get(LOCATION)?.to_str().ok()?;
+    url.join(location).ok()
+}
+
+// début de la plage renvoyée, d'après `Content-Range: bytes <début>-<fin>/<taille>`
+fn range_start(response: &Response) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread::{self, JoinHandle},
+    };
+
+    use anyhow::Result;
+    use sha2::{Digest, Sha256};
+    use tempfile::tempdir;
+
+    use super::{Sha, fetch};
+    use crate::{
+        interpol::{Env, InterpolableString},
+        resources::{auth::Auth, store::Store},
+    };
+
+    // sert les réponses dans l'ordre et retourne les en-têtes de chaque requête, en minuscules
+    fn serve(listener: TcpListener, responses: Vec<Vec<u8>>) -> JoinHandle<Vec<Vec<String>>> {
+        thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (response, stream) in responses.into_iter().zip(listener.incoming()) {
+                let mut stream = stream.unwrap();
+                let mut headers = Vec::new();
+                for line in BufReader::new(&stream).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    headers.push(line.to_lowercase());
+                }
+                stream.write_all(&response).unwrap();
+                requests.push(headers);
+            }
+            requests
+        })
+    }
+
+    fn ranges(requests: Vec<Vec<String>>) -> Vec<Option<String>> {
+        requests
+            .iter()
+            .map(|headers| {
+                headers
+                    .iter()
+                    .find_map(|h| h.strip_prefix("range: ").map(|r| r.to_string()))
+            })
+            .collect()
+    }
+
+    fn response(head: &str, body: &[u8]) -> Vec<u8> {
+        [format!("HTTP/1.1 {head}\r\n\r\n").as_bytes(), body].concat()
+    }
+
+    #[test]
+    fn test_sha_rejects_invalid_digest() {
+        let digest = format!("{:x}", Sha256::digest("echo tool"));
+        assert!(Sha::new(&Some(digest.clone()), &None).is_ok());
+        for invalid in [
+            "/opt/x".to_string(),
+            "abc".to_string(),
+            digest.to_uppercase(),
+            format!("{}/..", &digest[..61]),
+        ] {
+            assert!(Sha::new(&Some(invalid), &None).is_err());
+        }
+        assert!(Sha::new(&None, &Some(digest)).is_err());
+    }
+
+    // le serveur coupe la première réponse à mi-chemin, puis sert la suite sur requête Range
+    #[test]
+    fn test_fetch_resumes_after_interruption() -> Result<()> {
+        let content = (0..100_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let digest = format!("{:x}", Sha256::digest(&content));
+        let half = content.len() / 2;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let server = serve(
+            listener,
+            vec![
+                response(
+                    &format!("200 OK\r\nContent-Length: {}", content.len()),
+                    &content[..half],
+                ),
+                response(
+                    &format!(
+                        "206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}",
+                        content.len() - half,
+                        half,
+                        content.len() - 1,
+                        content.len()
+                    ),
+                    &content[half..],
+                ),
+            ],
+        );
+
+        let tmp = tempdir()?;
+        let partial = tmp.path().join("tool.part");
+        let sha256 = Some(digest);
+        let fetched = fetch(
+            &format!("http://127.0.0.1:{}/tool", port),
+            &None,
+            None,
+            &Env::new(),
+            &Store::new(tmp.path(), None),
+            &partial,
+            &Sha::new(&sha256, &None)?,
+        )?;
+        assert_eq!(std::fs::read(fetched.path())?, content);
+        assert_eq!(
+            ranges(server.join().unwrap()),
+            vec![None, Some("bytes=50000-".to_string())]
+        );
+        drop(fetched);
+        assert!(!partial.exists());
+        Ok(())
+    }
+
+    // une plage qui ne commence pas à la fin du fichier partiel fait tout retélécharger
+    #[test]
+    fn test_fetch_restarts_on_unexpected_range() -> Result<()> {
+        let content = (0..10_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let digest = format!("{:x}", Sha256::digest(&content));
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let server = serve(
+            listener,
+            vec![
+                response(
+                    &format!(
+                        "206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes 0-{}/{}",
+                        content.len(),
+                        content.len() - 1,
+                        content.len()
+                    ),
+                    &content,
+                ),
+                response(
+                    &format!("200 OK\r\nContent-Length: {}", content.len()),
+                    &content,
+                ),
+            ],
+        );
+
+        let tmp = tempdir()?;
+        let partial = tmp.path().join("tool.part");
+        std::fs::write(&partial, &content[..1000])?;
+        let sha256 = Some(digest);
+        let fetched = fetch(
+            &format!("http://127.0.0.1:{}/tool", port),
+            &None,
+            None,
+            &Env::new(),
+            &Store::new(tmp.path(), None),
+            &partial,
+            &Sha::new(&sha256, &None)?,
+        )?;
+        assert_eq!(std::fs::read(fetched.path())?, content);
+        assert_eq!(
+            ranges(server.join().unwrap()),
+            vec![Some("bytes=1000-".to_string()), None]
+        );
+        Ok(())
+    }
+
+    // les en-têtes personnalisés ne suivent pas une redirection vers un autre hôte
+    #[test]
+    fn test_fetch_drops_custom_headers_on_redirect() -> Result<()> {
+        let content = b"tool".to_vec();
+        let digest = format!("{:x}", Sha256::digest(&content));
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let server = serve(
+            listener,
+            vec![
+                response(
+                    &format!(
+                        "302 Found\r\nLocation: http://localhost:{port}/storage/tool\r\nContent-Length: 0"
+                    ),
+                    b"",
+                ),
+                response(
+                    &format!("200 OK\r\nContent-Length: {}", content.len()),
+                    &content,
+                ),
+            ],
+        );
+        let auth = Auth {
+            headers: BTreeMap::from([(
+                "PRIVATE-TOKEN".to_string(),
+                InterpolableString::new("secret".to_string()),
+            )]),
+            ..Default::default()
+        };
+
+        let tmp = tempdir()?;
+        let sha256 = Some(digest);
+        let fetched = fetch(
+            &format!("http://127.0.0.1:{}/tool", port),
+            &None,
+            Some(&auth),
+            &Env::new(),
+            &Store::new(tmp.path(), None),
+            &tmp.path().join("tool.part"),
+            &Sha::new(&sha256, &None)?,
+        )?;
+        assert_eq!(std::fs::read(fetched.path())?, content);
+        let requests = server.join().unwrap();
+        assert!(requests[0].contains(&"private-token: secret".to_string()));
+        assert!(requests[1][0].starts_with("get /storage/tool"));
+        assert!(!requests[1].iter().any(|h| h.starts_with("private-token")));
+        Ok(())
+    }
+}
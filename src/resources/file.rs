@@ -1,29 +1,30 @@
 use std::{
+    collections::HashMap,
     fs,
-    io::{self, BufReader, Write},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
-use anyhow::{Context, Result, anyhow, bail};
+use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256, Sha512};
-use tempfile::tempdir;
-use url::Url;
 
-use crate::interpol::{Env, InterpolableString};
-
-use super::Substrate;
+use crate::{
+    Platform,
+    interpol::{Env, InterpolableString},
+    lock::LockedResource,
+    progress,
+};
 
-#[cfg(target_family = "unix")]
-use nix::sys::statvfs::statvfs;
-#[cfg(target_family = "windows")]
-use std::os::windows::ffi::OsStrExt;
-#[cfg(target_family = "windows")]
-use winapi::um::fileapi::GetVolumePathNameW;
+use super::{
+    OfflineError, Substrate,
+    auth::Auth,
+    fetch::{Fetched, Sha, fetch},
+    store::{self, Store},
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct File {
-    pub url: InterpolableString,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<Urls>,
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub sha256: Option<String>,
@@ -31,6 +32,11 @@ pub struct File {
     pub sha512: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub proxy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<Auth>,
+    // variantes indexées par plateforme (ex: linux-x86_64), prioritaires sur url
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platforms: Option<HashMap<String, Variant>>,
     #[serde(default = "default_false")]
     #[serde(skip_serializing_if = "field_is_false")]
     pub archive: bool,
@@ -39,6 +45,43 @@ pub struct File {
     pub executable: bool,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Variant {
+    pub url: Urls,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha512: Option<String>,
+}
+
+// une url, ou une liste de miroirs essayés dans l'ordre : le hash garantit qu'ils sont équivalents
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Urls {
+    One(InterpolableString),
+    Mirrors(Vec<InterpolableString>),
+}
+
+impl Urls {
+    pub fn iter(&self) -> impl Iterator<Item = &InterpolableString> {
+        match self {
+            Urls::One(url) => std::slice::from_ref(url).iter(),
+            Urls::Mirrors(urls) => urls.iter(),
+        }
+    }
+
+    // url de référence, enregistrée dans le fichier de verrouillage
+    fn primary(&self) -> Result<&InterpolableString> {
+        self.iter().next().context("Need at least one url")
+    }
+}
+
+impl From<InterpolableString> for Urls {
+    fn from(url: InterpolableString) -> Self {
+        Urls::One(url)
+    }
+}
+
 fn field_is_false(v: &bool) -> bool {
     !v
 }
@@ -64,118 +107,32 @@ fn set_executable(path: &Path) -> Result<()> {
     Ok(())
 }
 
-fn move_file(src: &Path, dest: &Path) -> Result<()> {
-    let dest_parent = dest
-        .parent()
-        .with_context(|| format!("cannot find parent for {dest:?}"))?;
-    if is_on_same_fs(src, dest_parent)
-        .with_context(|| format!("Cannot determine if {src:?} and {dest:?} are on the same disk"))?
-    {
-        // Same partition, use rename
-        fs::rename(src, dest)?;
-    } else {
-        // Different partitions, use copy and remove
-        fs::copy(src, dest)?;
-        fs::remove_file(src)?;
-    }
-    Ok(())
-}
-
-fn is_on_same_fs(src: &Path, dest: &Path) -> Result<bool> {
-    #[cfg(target_family = "unix")]
-    {
-        // println!(
-        //     "src: {:?}, dest: {:?}",
-        //     statvfs(src)?.filesystem_id(),
-        //     statvfs(dest)?.filesystem_id()
-        // );
-        let src_stat = statvfs(src)?;
-        let dest_stat = statvfs(dest)?;
-        Ok(src_stat.filesystem_id() == dest_stat.filesystem_id())
-    }
-
-    #[cfg(target_family = "windows")]
-    {
-        let src_volume = get_volume_path(src)?;
-        let dest_volume = get_volume_path(dest)?;
-        Ok(src_volume == dest_volume)
-    }
-}
-
-#[cfg(target_family = "windows")]
-fn get_volume_path(path: &Path) -> Result<String> {
-    let mut volume_path = vec![0u16; winapi::shared::minwindef::MAX_PATH];
-    let path_str: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
-    unsafe {
-        if GetVolumePathNameW(
-            path_str.as_ptr(),
-            volume_path.as_mut_ptr(),
-            volume_path.len() as u32,
-        ) == 0
-        {
-            return Err(io::Error::last_os_error())?;
-        }
-    }
-    let volume_path_str = String::from_utf16_lossy(&volume_path);
-    Ok(volume_path_str.trim_end_matches('\u{0}').to_string())
-}
-
-#[derive(Debug, Clone, Copy)]
-enum Sha<'a> {
-    Sha256(&'a String),
-    Sha512(&'a String),
-}
-
-impl<'a> Sha<'a> {
-    fn compare(&self, path: &Path) -> Result<bool> {
-        match self {
-            Sha::Sha256(s) => {
-                let mut hasher = Sha256::new();
-                let file = std::fs::File::open(path)?;
-                let mut reader = BufReader::new(file);
-                io::copy(&mut reader, &mut hasher)?;
-                let digest = hasher.finalize();
-                let digest = format!("{:x}", digest);
-                Ok(&digest == *s)
-            }
-            Sha::Sha512(s) => {
-                let mut hasher = Sha512::new();
-                let file = std::fs::File::open(path)?;
-                let mut reader = BufReader::new(file);
-                io::copy(&mut reader, &mut hasher)?;
-                let digest = hasher.finalize();
-                let digest = format!("{:x}", digest);
-                Ok(&digest == *s)
-            }
+impl File {
+    fn source(&self, platform: &str) -> Result<(&Urls, Sha<'_>)> {
+        if let Some(variant) = self.platforms.as_ref().and_then(|p| p.get(platform)) {
+            return Ok((&variant.url, Sha::new(&variant.sha256, &variant.sha512)?));
         }
-    }
-
-    fn small(&self) -> &'a str {
-        match self {
-            Sha::Sha256(s) => &s[..16],
-            Sha::Sha512(s) => &s[..16],
+        match &self.url {
+            Some(url) => Ok((url, Sha::new(&self.sha256, &self.sha512)?)),
+            None => match &self.platforms {
+                Some(platforms) => {
+                    let mut available = platforms.keys().map(|k| k.as_str()).collect::<Vec<_>>();
+                    available.sort();
+                    bail!(
+                        "No variant of {} for platform {} (available: {})",
+                        self.name,
+                        platform,
+                        available.join(", ")
+                    )
+                }
+                None => bail!("Need url or platforms"),
+            },
         }
     }
-}
 
-impl File {
-    fn import_file(
-        &self,
-        path: &Path,
-        repo_location: &Path,
-        orig_url: String,
-        copy: bool,
-        sha: &Sha,
-    ) -> Result<()> {
-        let output_dir = repo_location.join(sha.small());
-        if !path.exists() {
-            bail!("File {:?} is missing", path)
-        }
+    fn import_file(&self, path: &Path, output_dir: &Path, copy: bool) -> Result<()> {
         let dest = output_dir.join(&self.name);
-        if !sha.compare(path)? {
-            bail!("URL {} must have hash equal to {:?}", orig_url, sha)
-        }
-        fs::create_dir_all(&output_dir)?;
+        fs::create_dir_all(output_dir)?;
         if self.archive {
             mkar::unarchive(path, dest)?;
         } else {
@@ -183,7 +140,7 @@ impl File {
                 std::fs::copy(path, &dest)
                     .with_context(|| format!("Cannot copy {:?} into {:?}", path, dest))?;
             } else {
-                move_file(path, &dest)
+                fs::rename(path, &dest)
                     .with_context(|| format!("Cannot move {:?} into {:?}", path, dest))?;
             }
             if self.executable {
@@ -194,53 +151,220 @@ impl File {
         Ok(())
     }
 
-    pub fn ensure_resources(&self, env: &Env, repo_location: &Path) -> Result<Substrate> {
-        let sha = match (&self.sha256, &self.sha512) {
-            (None, None) => bail!("Need sha256 or sha512"),
-            (None, Some(s)) => Sha::Sha512(s),
-            (Some(s), None) => Sha::Sha256(s),
-            (Some(_), Some(_)) => bail!("Cannot have both sha256 and sha512"),
-        };
-        let output_dir = repo_location.join(sha.small());
+    pub fn ensure_resources(
+        &self,
+        env: &Env,
+        store: &Store,
+        repo_location: Option<&Path>,
+    ) -> Result<(Substrate, bool)> {
+        let (url, sha) = self.source(&Platform::key())?;
+        let output_dir = store.entry(repo_location, sha.digest());
         let output_file = output_dir.join(&self.name);
         let substrate = Substrate::new(
-            std::path::absolute(output_dir)?
+            std::path::absolute(&output_dir)?
                 .to_string_lossy()
                 .to_string(),
         );
         if output_file.exists() {
-            return Ok(substrate);
+            return Ok((substrate, false));
         }
-        let url_str = self.url.interpolate(env)?;
-        let url = url_str.parse::<Url>()?;
-        println!("Get: {}", url);
-        if url.scheme() == "file" {
-            let path = url
-                .to_file_path()
-                .map_err(|()| anyhow!("Url {} is not a file", url))?;
-            self.import_file(&path, repo_location, url_str, true, &sha)?;
-            return Ok(substrate);
+        let _lock = store::lock_entry(&output_dir)?;
+        // installé par un autre processus pendant l'attente du verrou
+        if output_file.exists() {
+            return Ok((substrate, false));
+        }
+        let partial = store::partial_download(&output_dir)?;
+        let mirrors = url.iter().count();
+        let mut errors = Vec::new();
+        let mut fetched = None;
+        for (i, url) in url.iter().enumerate() {
+            let url_str = url.interpolate(env)?;
+            match fetch(
+                &url_str,
+                &self.proxy,
+                self.auth.as_ref(),
+                env,
+                store,
+                &partial,
+                &sha,
+            ) {
+                Ok(f) => {
+                    fetched = Some(f);
+                    break;
+                }
+                Err(e) => {
+                    if i + 1 < mirrors && !e.is::<OfflineError>() {
+                        progress::message(&format!(
+                            "Failed: {} ({:#}), trying next mirror",
+                            url_str, e
+                        ));
+                    }
+                    errors.push(e);
+                }
+            }
+        }
+        let Some(fetched) = fetched else {
+            // hors ligne sans miroir local : la ressource est simplement absente du store
+            if errors.iter().all(|e| e.is::<OfflineError>())
+                && let Some(e) = errors.pop()
+            {
+                return Err(e);
+            }
+            let errors = errors
+                .iter()
+                .map(|e| format!("{:#}", e))
+                .collect::<Vec<_>>();
+            bail!("Cannot fetch {}: {}", self.name, errors.join("; "));
+        };
+        let staging = store::staging(&output_dir)?;
+        let staged = staging.path().join("entry");
+        match &fetched {
+            Fetched::Local(path) => self.import_file(path, &staged, true)?,
+            Fetched::Downloaded(path) => self.import_file(path, &staged, false)?,
         }
-        if url.scheme() == "http" || url.scheme() == "https" {
-            let tmpdir = tempdir()?;
-            let file_path = tmpdir.path().join(&self.name);
-            let client = match &self.proxy {
-                Some(proxy) => reqwest::blocking::Client::builder()
-                    .proxy(reqwest::Proxy::all(proxy)?)
-                    .build()?,
-                None => reqwest::blocking::Client::new(),
-            };
-            let body = client.get(url.clone()).send()?;
-            let mut body_reader = BufReader::new(body);
-            let mut file = std::fs::File::create_new(&file_path)?;
-            io::copy(&mut body_reader, &mut file)?;
-            file.flush()?;
-            self.import_file(&file_path, repo_location, url_str, false, &sha)?;
-            return Ok(substrate);
+        if output_dir.exists() {
+            // entrée partagée avec un fichier de même hash mais de nom différent
+            store::add_to_entry(&staged, &output_dir, &self.name)?;
+        } else {
+            store::commit(&staged, &output_dir)?;
+        }
+        Ok((substrate, true))
+    }
+
+    pub fn entries(&self, store: &Store, repo_location: Option<&Path>) -> Result<Vec<PathBuf>> {
+        let mut result = Vec::new();
+        if self.url.is_some() {
+            let sha = Sha::new(&self.sha256, &self.sha512)?;
+            result.push(store.entry(repo_location, sha.digest()));
+        }
+        for variant in self.platforms.iter().flat_map(|p| p.values()) {
+            let sha = Sha::new(&variant.sha256, &variant.sha512)?;
+            result.push(store.entry(repo_location, sha.digest()));
         }
-        bail!("Unsupported scheme {}", url.scheme());
+        Ok(result)
     }
+
+    fn locked(
+        &self,
+        url: &Urls,
+        sha: Sha,
+        env: &Env,
+        store: &Store,
+        repo_location: Option<&Path>,
+    ) -> Result<LockedResource> {
+        Ok(LockedResource {
+            url: url.primary()?.interpolate(env)?,
+            algorithm: sha.algorithm().to_string(),
+            digest: sha.digest().to_string(),
+            archive: self.archive,
+            executable: self.executable,
+            store: store.lock_path(repo_location, sha.digest()),
+        })
+    }
+
+    pub fn lock(
+        &self,
+        env: &Env,
+        store: &Store,
+        repo_location: Option<&Path>,
+    ) -> Result<Vec<(Option<&str>, LockedResource)>> {
+        let mut result = Vec::new();
+        if let Some(url) = &self.url {
+            let sha = Sha::new(&self.sha256, &self.sha512)?;
+            result.push((None, self.locked(url, sha, env, store, repo_location)?));
+        }
+        if let Some(platforms) = &self.platforms {
+            for (platform, variant) in platforms {
+                let sha = Sha::new(&variant.sha256, &variant.sha512)?;
+                result.push((
+                    Some(platform.as_str()),
+                    self.locked(&variant.url, sha, env, store, repo_location)?,
+                ));
+            }
+        }
+        Ok(result)
+    }
+
     pub fn get_dependances(&self) -> Vec<&str> {
-        self.url.get_variables()
+        self.url
+            .iter()
+            .chain(
+                self.platforms
+                    .iter()
+                    .flat_map(|p| p.values().map(|v| &v.url)),
+            )
+            .flat_map(|urls| urls.iter())
+            .flat_map(|url| url.get_variables())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use anyhow::Result;
+    use tempfile::tempdir;
+    use url::Url;
+
+    use super::File;
+    use crate::{interpol::Env, resources::store::Store, test_utils::tool};
+
+    #[test]
+    fn test_source_by_platform() -> Result<()> {
+        let file: File = serde_yaml::from_str(
+            r#"
+name: k9s
+archive: true
+url: https://example.com/k9s_Linux_amd64.tar.gz
+sha256: 2f69f26eb1c65727e177daca30747a5832b7f39c68280a557d684ef9a25f5b34
+platforms:
+  windows-x86_64:
+    url: https://example.com/k9s_Windows_amd64.zip
+    sha256: d2bfdc39328fb7fc9a1568292cba8d5b63ba154c304f8b53aece16bf35517abf
+"#,
+        )?;
+        let (url, sha) = file.source("windows-x86_64")?;
+        assert_eq!(
+            url.primary()?.interpolate(&Env::new())?,
+            "https://example.com/k9s_Windows_amd64.zip"
+        );
+        assert_eq!(
+            sha.digest(),
+            "d2bfdc39328fb7fc9a1568292cba8d5b63ba154c304f8b53aece16bf35517abf"
+        );
+        let (_, sha) = file.source("linux-x86_64")?;
+        assert_eq!(
+            sha.digest(),
+            "2f69f26eb1c65727e177daca30747a5832b7f39c68280a557d684ef9a25f5b34"
+        );
+
+        let file = File { url: None, ..file };
+        assert!(file.source("linux-x86_64").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_mirrors() -> Result<()> {
+        let tmp = tempdir()?;
+        let tool = tool(tmp.path())?;
+        let missing = Url::from_file_path(tmp.path().join("missing.sh")).unwrap();
+        let file: File = serde_yaml::from_str(&format!(
+            r#"
+name: tool
+url:
+  - {missing}
+  - {mirror}
+sha256: {digest}
+"#,
+            mirror = tool.url,
+            digest = tool.sha256,
+        ))?;
+        let store = Store::new(tmp.path(), None);
+        let (substrate, _) = file.ensure_resources(&Env::new(), &store, None)?;
+        assert!(Path::new(&substrate.to_string()).join("tool").exists());
+        let (_, locked) = file.lock(&Env::new(), &store, None)?.remove(0);
+        assert_eq!(locked.url, missing.to_string());
+        Ok(())
     }
 }
@@ -1,18 +1,29 @@
+pub mod archive;
+pub mod auth;
+mod fetch;
 pub mod file;
+pub mod git;
+pub mod store;
 
-use std::path::{Path, PathBuf};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{Result, anyhow};
 use jsonpath_rust::JsonPath;
 use serde::{Deserialize, Serialize};
 
-use crate::interpol::{Env, InterpolableString};
+use crate::{interpol::Env, lock::LockedResource};
+use store::Store;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum Resource {
     Archive {
-        url: InterpolableString,
-        sha256: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        repo_location: Option<PathBuf>,
+        #[serde(flatten)]
+        archive: archive::Archive,
     },
     File {
         #[serde(skip_serializing_if = "Option::is_none")]
@@ -21,8 +32,10 @@ pub enum Resource {
         file: file::File,
     },
     Git {
-        url: InterpolableString,
-        commit: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        repo_location: Option<PathBuf>,
+        #[serde(flatten)]
+        git: git::Git,
     },
 }
 
@@ -53,24 +66,86 @@ impl Substrate {
     }
 }
 
+// accès réseau refusé en mode hors ligne : la ressource n'est pas dans le store
+#[derive(Debug)]
+pub struct OfflineError {
+    pub url: String,
+}
+
+impl fmt::Display for OfflineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is not available offline", self.url)
+    }
+}
+
+impl std::error::Error for OfflineError {}
+
 impl Resource {
-    pub fn ensure_resources(&self, env: &Env, config_parent: &Path) -> Result<Substrate> {
-        match self {
-            Resource::Archive { url, sha256 } => todo!(),
-            Resource::Git { url, commit } => todo!(),
+    // vrai si l'entrée vient d'être installée, faux si elle était déjà dans le store
+    pub fn ensure_resources(&self, env: &Env, store: &Store) -> Result<(Substrate, bool)> {
+        let (substrate, installed) = match self {
+            Resource::Archive {
+                repo_location,
+                archive,
+            } => archive.ensure_resources(env, store, repo_location.as_deref()),
+            Resource::Git { repo_location, git } => {
+                git.ensure_resources(env, store, repo_location.as_deref())
+            }
             Resource::File {
                 repo_location,
                 file,
-            } => {
-                let repo_location = repo_location.clone().unwrap_or("./.chenv".into());
-                file.ensure_resources(env, &config_parent.join(&repo_location))
+            } => file.ensure_resources(env, store, repo_location.as_deref()),
+        }?;
+        if let Err(e) = store::touch(Path::new(&substrate.to_string())) {
+            log::warn!("Cannot record use of {}: {}", substrate.to_string(), e);
+        }
+        Ok((substrate, installed))
+    }
+    // toutes les entrées du store que la ressource peut utiliser, quelle que soit la plateforme
+    pub fn entries(&self, store: &Store) -> Result<Vec<PathBuf>> {
+        match self {
+            Resource::Archive {
+                repo_location,
+                archive,
+            } => Ok(vec![archive.entry(store, repo_location.as_deref())?]),
+            Resource::Git { repo_location, git } => {
+                Ok(vec![git.entry(store, repo_location.as_deref())?])
             }
+            Resource::File {
+                repo_location,
+                file,
+            } => file.entries(store, repo_location.as_deref()),
+        }
+    }
+    pub fn lock(&self, env: &Env, store: &Store) -> Result<Vec<(Option<&str>, LockedResource)>> {
+        match self {
+            Resource::Archive {
+                repo_location,
+                archive,
+            } => Ok(vec![(
+                None,
+                archive.lock(env, store, repo_location.as_deref())?,
+            )]),
+            Resource::Git { repo_location, git } => Ok(vec![(
+                None,
+                git.lock(env, store, repo_location.as_deref())?,
+            )]),
+            Resource::File {
+                repo_location,
+                file,
+            } => file.lock(env, store, repo_location.as_deref()),
         }
     }
     pub fn get_dependances(&self) -> Vec<&str> {
         match self {
-            Resource::Archive { url, sha256 } => todo!(),
-            Resource::Git { url, commit } => todo!(),
+            Resource::Archive {
+                repo_location: _,
+                archive,
+            } => archive.get_dependances(),
+            Resource::Git {
+                repo_location: _,
+                git,
+            } => git.get_dependances(),
             Resource::File {
                 repo_location: _,
                 file,
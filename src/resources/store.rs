@@ -0,0 +1,489 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tempfile::{NamedTempFile, TempDir};
+
+use crate::{lock::store_path, progress, user_config::UserConf};
+
+// répertoire des métadonnées des entrées, à la racine de chaque store
+pub const META_DIR: &str = ".meta";
+
+const DEFAULT_REPO_LOCATION: &str = "./.chenv";
+
+// Les entrées sont rangées dans le store du dépôt (<repo_location>/<16 premiers caractères du hash>)
+// ou, si l'utilisateur l'a activé, dans le store global (<store>/<hash complet>).
+// Un repo_location explicite sur la ressource est toujours prioritaire.
+#[derive(Debug, Clone)]
+pub struct Store {
+    config_parent: PathBuf,
+    global: Option<PathBuf>,
+    offline: bool,
+    // réécritures, identifiants, proxy et TLS des téléchargements
+    user_conf: Arc<UserConf>,
+}
+
+impl Store {
+    pub fn new(config_parent: &Path, global: Option<PathBuf>) -> Self {
+        Self {
+            config_parent: config_parent.to_owned(),
+            global,
+            offline: false,
+            user_conf: Arc::default(),
+        }
+    }
+
+    // sans accès réseau, le store ne peut être rempli qu'à partir d'urls file://
+    pub fn with_offline(self, offline: bool) -> Self {
+        Self { offline, ..self }
+    }
+
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
+    pub fn with_user_conf(self, user_conf: UserConf) -> Self {
+        Self {
+            user_conf: Arc::new(user_conf),
+            ..self
+        }
+    }
+
+    pub fn user_conf(&self) -> &UserConf {
+        &self.user_conf
+    }
+
+    pub fn entry(&self, repo_location: Option<&Path>, digest: &str) -> PathBuf {
+        match (repo_location, &self.global) {
+            (None, Some(global)) => global.join(digest),
+            (repo_location, _) => self
+                .config_parent
+                .join(repo_location.unwrap_or(Path::new(DEFAULT_REPO_LOCATION)))
+                .join(&digest[..16]),
+        }
+    }
+
+    pub fn global(&self) -> Option<&Path> {
+        self.global.as_deref()
+    }
+
+    pub fn default_location(&self) -> PathBuf {
+        self.config_parent.join(DEFAULT_REPO_LOCATION)
+    }
+
+    // chemin de l'entrée relatif à la configuration, None pour le store global
+    pub fn lock_path(&self, repo_location: Option<&Path>, digest: &str) -> Option<String> {
+        match (repo_location, &self.global) {
+            (None, Some(_)) => None,
+            (repo_location, _) => Some(store_path(
+                &repo_location
+                    .unwrap_or(Path::new(DEFAULT_REPO_LOCATION))
+                    .join(&digest[..16]),
+            )),
+        }
+    }
+}
+
+// fichier dont la date de modification indique la dernière utilisation de l'entrée
+pub fn used_marker(entry: &Path) -> Option<PathBuf> {
+    meta_file(entry, "used")
+}
+
+pub fn touch(entry: &Path) -> Result<()> {
+    let marker = used_marker(entry).with_context(|| format!("Invalid store entry {:?}", entry))?;
+    if let Some(parent) = marker.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = fs::File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&marker)?;
+    file.set_modified(SystemTime::now())?;
+    Ok(())
+}
+
+// téléchargement en cours pour l'entrée, conservé pour être repris après une interruption
+pub fn partial_download(entry: &Path) -> Result<PathBuf> {
+    meta_file(entry, "part").with_context(|| format!("Invalid store entry {:?}", entry))
+}
+
+pub fn last_used(entry: &Path) -> Result<SystemTime> {
+    match used_marker(entry).filter(|marker| marker.exists()) {
+        Some(marker) => Ok(fs::metadata(marker)?.modified()?),
+        None => Ok(fs::symlink_metadata(entry)?.modified()?),
+    }
+}
+
+fn lock_file(entry: &Path) -> Result<fs::File> {
+    let path =
+        meta_file(entry, "lock").with_context(|| format!("Invalid store entry {:?}", entry))?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(fs::File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)?)
+}
+
+// verrou consultatif sur l'entrée, relâché quand le fichier retourné est fermé
+pub fn lock_entry(entry: &Path) -> Result<fs::File> {
+    let file = lock_file(entry)?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(fs::TryLockError::WouldBlock) => {
+            progress::message(&format!(
+                "Waiting for {:?}, locked by another process",
+                entry
+            ));
+            file.lock()
+                .with_context(|| format!("Cannot lock {:?}", entry))?;
+        }
+        Err(fs::TryLockError::Error(e)) => {
+            return Err(e).with_context(|| format!("Cannot lock {:?}", entry));
+        }
+    }
+    Ok(file)
+}
+
+// None si l'entrée est verrouillée par un autre processus
+pub fn try_lock_entry(entry: &Path) -> Result<Option<fs::File>> {
+    let file = lock_file(entry)?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(file)),
+        Err(fs::TryLockError::WouldBlock) => Ok(None),
+        Err(fs::TryLockError::Error(e)) => {
+            Err(e).with_context(|| format!("Cannot lock {:?}", entry))
+        }
+    }
+}
+
+fn meta_file(entry: &Path, suffix: &str) -> Option<PathBuf> {
+    let name = entry.file_name()?.to_string_lossy().to_string();
+    Some(
+        entry
+            .parent()?
+            .join(META_DIR)
+            .join(format!("{name}.{suffix}")),
+    )
+}
+
+// répertoire contenant des entrées installées par chenv ; un repo_location peut désigner un
+// répertoire quelconque, par exemple la racine du dépôt
+pub fn is_store(dir: &Path) -> bool {
+    dir.join(META_DIR).is_dir()
+}
+
+// une entrée est nommée d'après un hash tronqué (store du dépôt), un hash complet ou un commit
+// (store global) ; une entrée récente a aussi des métadonnées
+fn is_entry(path: &Path) -> bool {
+    let Some(name) = path.file_name().map(|n| n.to_string_lossy()) else {
+        return false;
+    };
+    let hash_like =
+        matches!(name.len(), 16 | 40 | 64 | 128) && name.bytes().all(|b| b.is_ascii_hexdigit());
+    hash_like
+        || ["manifest", "used"]
+            .iter()
+            .any(|suffix| meta_file(path, suffix).is_some_and(|m| m.exists()))
+}
+
+// entrées du store, à l'exclusion de tout autre fichier ou répertoire qui s'y trouverait
+pub fn list_entries(store: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+    if !store.is_dir() {
+        return Ok(entries);
+    }
+    for entry in fs::read_dir(store)? {
+        let entry = entry?.path();
+        if entry
+            .file_name()
+            .is_some_and(|n| !n.to_string_lossy().starts_with('.'))
+            && is_entry(&entry)
+        {
+            entries.push(entry);
+        }
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+pub fn remove_entry(entry: &Path) -> Result<()> {
+    if fs::symlink_metadata(entry)?.is_dir() {
+        fs::remove_dir_all(entry)?;
+    } else {
+        fs::remove_file(entry)?;
+    }
+    let name = entry.file_name().unwrap_or_default().to_string_lossy();
+    if let Some(meta) = entry.parent().map(|p| p.join(META_DIR)) {
+        for meta_file in fs::read_dir(&meta).into_iter().flatten() {
+            let meta_file = meta_file?.path();
+            let meta_name = meta_file.file_name().unwrap_or_default().to_string_lossy();
+            // le verrou est conservé : il peut être détenu par l'appelant
+            if meta_name.starts_with(&format!("{name}.")) && !meta_name.ends_with(".lock") {
+                fs::remove_file(&meta_file)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// chemin relatif -> sha256 du contenu, ou cible pour un lien symbolique
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct Manifest(BTreeMap<String, String>);
+
+impl Manifest {
+    fn add(&mut self, root: &Path, path: &Path) -> Result<()> {
+        let metadata = fs::symlink_metadata(path)?;
+        let relative = path
+            .strip_prefix(root)?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect::<Vec<_>>()
+            .join("/");
+        if metadata.is_symlink() {
+            let target = fs::read_link(path)?;
+            self.0
+                .insert(relative, format!("-> {}", target.to_string_lossy()));
+        } else if metadata.is_dir() {
+            for child in fs::read_dir(path)? {
+                let child = child?.path();
+                // le dépôt git d'une ressource Git évolue à chaque commande git
+                if path == root && child.file_name().is_some_and(|n| n == ".git") {
+                    continue;
+                }
+                self.add(root, &child)?;
+            }
+        } else {
+            let mut hasher = Sha256::new();
+            io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+            self.0.insert(relative, format!("{:x}", hasher.finalize()));
+        }
+        Ok(())
+    }
+
+    // retire un fichier ou un répertoire de premier niveau de l'entrée
+    fn remove(&mut self, name: &str) {
+        self.0
+            .retain(|path, _| path != name && !path.starts_with(&format!("{name}/")));
+    }
+
+    pub fn compute(entry: &Path) -> Result<Self> {
+        let mut manifest = Manifest::default();
+        manifest.add(entry, entry)?;
+        Ok(manifest)
+    }
+
+    // différences entre le manifeste enregistré (self) et le contenu actuel
+    pub fn diff(&self, actual: &Manifest) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (path, hash) in &self.0 {
+            match actual.0.get(path) {
+                None => problems.push(format!("missing {}", path)),
+                Some(h) if h != hash => problems.push(format!("modified {}", path)),
+                Some(_) => {}
+            }
+        }
+        for path in actual.0.keys() {
+            if !self.0.contains_key(path) {
+                problems.push(format!("unexpected {}", path));
+            }
+        }
+        problems
+    }
+}
+
+fn manifest_path(entry: &Path) -> Result<PathBuf> {
+    meta_file(entry, "manifest").with_context(|| format!("Invalid store entry {:?}", entry))
+}
+
+fn read_manifest(path: &Path) -> Result<Manifest> {
+    serde_json::from_str(&fs::read_to_string(path)?)
+        .with_context(|| format!("Cannot read {:?}", path))
+}
+
+// remplacé d'un bloc : un manifeste n'est jamais à moitié écrit
+fn save_manifest(path: &Path, manifest: &Manifest) -> Result<()> {
+    let parent = path
+        .parent()
+        .with_context(|| format!("Invalid manifest path {:?}", path))?;
+    fs::create_dir_all(parent)?;
+    let mut file = NamedTempFile::new_in(parent)?;
+    serde_json::to_writer_pretty(&mut file, manifest)?;
+    file.persist(path)?;
+    Ok(())
+}
+
+// le contenu peut être encore en préparation, hors de l'entrée
+pub fn write_manifest(entry: &Path, content: &Path) -> Result<()> {
+    save_manifest(&manifest_path(entry)?, &Manifest::compute(content)?)
+}
+
+// ajoute le fichier name préparé dans staged à une entrée déjà installée, par exemple un fichier
+// de même hash mais de nom différent : le reste de l'entrée doit correspondre au manifeste
+// enregistré, auquel seul le nouveau fichier est ajouté avant d'être mis en place
+pub fn add_to_entry(staged: &Path, entry: &Path, name: &str) -> Result<()> {
+    let path = manifest_path(entry)?;
+    // sans manifeste, l'entrée reste non vérifiable
+    if path.exists() {
+        let mut recorded = read_manifest(&path)?;
+        let mut actual = Manifest::compute(entry)?;
+        // une installation interrompue a pu enregistrer le fichier sans le mettre en place
+        recorded.remove(name);
+        actual.remove(name);
+        let problems = recorded.diff(&actual);
+        if !problems.is_empty() {
+            bail!(
+                "Store entry {:?} does not match its manifest ({}), run `chenv verify --fix` to repair it",
+                entry,
+                problems.join(", ")
+            );
+        }
+        let mut added = Manifest::default();
+        added.add(staged, &staged.join(name))?;
+        recorded.0.extend(added.0);
+        save_manifest(&path, &recorded)?;
+    }
+    let dest = entry.join(name);
+    match fs::rename(staged.join(name), &dest) {
+        Ok(()) => Ok(()),
+        Err(_) if dest.exists() => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Cannot move file into {:?}", dest)),
+    }
+}
+
+// répertoire de préparation d'une installation, à côté de l'entrée pour pouvoir la renommer
+pub fn staging(entry: &Path) -> Result<TempDir> {
+    let parent = entry
+        .parent()
+        .with_context(|| format!("Invalid store entry {:?}", entry))?;
+    fs::create_dir_all(parent)?;
+    let name = entry.file_name().unwrap_or_default().to_string_lossy();
+    Ok(tempfile::Builder::new()
+        .prefix(&format!(".tmp-{name}-"))
+        .tempdir_in(parent)?)
+}
+
+// met en place l'entrée préparée dans staged, une fois complète
+pub fn commit(staged: &Path, entry: &Path) -> Result<()> {
+    write_manifest(entry, staged)?;
+    match fs::rename(staged, entry) {
+        Ok(()) => Ok(()),
+        // installée entre-temps par un autre processus
+        Err(_) if entry.exists() => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Cannot move {:?} into {:?}", staged, entry)),
+    }
+}
+
+// liste les problèmes de l'entrée, vide si elle est intacte ; None si elle n'a pas de manifeste
+pub fn verify_entry(entry: &Path) -> Result<Option<Vec<String>>> {
+    let path = manifest_path(entry)?;
+    // installée par une version de chenv antérieure aux manifestes : rien à comparer
+    if !path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(read_manifest(&path)?.diff(&Manifest::compute(entry)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use super::{Store, add_to_entry, lock_entry, try_lock_entry, verify_entry, write_manifest};
+
+    #[test]
+    fn test_store_entry() {
+        let digest = "bb16739fcad964c197752200ff89d89aad7b118cb1de5725dc53fe924c40e3f7";
+        let repo = Store::new(Path::new("/repo"), None);
+        assert_eq!(
+            repo.entry(None, digest),
+            Path::new("/repo/./.chenv/bb16739fcad964c1")
+        );
+        assert_eq!(
+            repo.lock_path(None, digest).as_deref(),
+            Some(".chenv/bb16739fcad964c1")
+        );
+
+        let global = Store::new(Path::new("/repo"), Some("/cache/store".into()));
+        assert_eq!(
+            global.entry(None, digest),
+            Path::new("/cache/store").join(digest)
+        );
+        assert_eq!(global.lock_path(None, digest), None);
+        assert_eq!(
+            global.entry(Some(Path::new("tools")), digest),
+            Path::new("/repo/tools/bb16739fcad964c1")
+        );
+    }
+
+    #[test]
+    fn test_verify_entry() -> Result<()> {
+        let tmp = tempdir()?;
+        let entry = tmp.path().join("0123456789abcdef");
+        fs::create_dir_all(entry.join("bin"))?;
+        fs::write(entry.join("bin").join("tool"), "tool")?;
+        fs::write(entry.join("README"), "readme")?;
+        assert_eq!(verify_entry(&entry)?, None);
+
+        write_manifest(&entry, &entry)?;
+        assert_eq!(verify_entry(&entry)?, Some(vec![]));
+
+        fs::write(entry.join("bin").join("tool"), "tampered")?;
+        fs::remove_file(entry.join("README"))?;
+        fs::write(entry.join("extra"), "")?;
+        assert_eq!(
+            verify_entry(&entry)?,
+            Some(vec![
+                "missing README".to_string(),
+                "modified bin/tool".to_string(),
+                "unexpected extra".to_string()
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_to_entry() -> Result<()> {
+        let tmp = tempdir()?;
+        let entry = tmp.path().join("0123456789abcdef");
+        fs::create_dir_all(&entry)?;
+        fs::write(entry.join("tool"), "tool")?;
+        write_manifest(&entry, &entry)?;
+        let staged = tmp.path().join("staged");
+        fs::create_dir_all(&staged)?;
+
+        fs::write(staged.join("alias"), "tool")?;
+        add_to_entry(&staged, &entry, "alias")?;
+        assert_eq!(verify_entry(&entry)?, Some(vec![]));
+
+        // le fichier ajouté n'approuve pas une modification du reste de l'entrée
+        fs::write(entry.join("tool"), "tampered")?;
+        fs::write(staged.join("other"), "tool")?;
+        assert!(add_to_entry(&staged, &entry, "other").is_err());
+        assert!(!entry.join("other").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_entry() -> Result<()> {
+        let tmp = tempdir()?;
+        let entry = tmp.path().join("0123456789abcdef");
+        let lock = lock_entry(&entry)?;
+        assert!(try_lock_entry(&entry)?.is_none());
+        drop(lock);
+        assert!(try_lock_entry(&entry)?.is_some());
+        Ok(())
+    }
+}
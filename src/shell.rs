@@ -0,0 +1,154 @@
+use std::{collections::BTreeMap, env, fs, path::Path, process::Command};
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tempfile::{TempDir, tempdir};
+
+use crate::{
+    Os,
+    export::{self, Format, fish_quote, posix_quote},
+};
+
+pub const DEFAULT_PROMPT: &str = "(chenv) ";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    #[serde(alias = "nushell")]
+    #[value(alias = "nushell")]
+    Nu,
+    Cmd,
+}
+
+impl Shell {
+    pub fn detect(os: Os) -> Shell {
+        let name = env::var_os("SHELL").and_then(|shell| {
+            Path::new(&shell)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+        });
+        match name.as_deref() {
+            Some("bash") => Shell::Bash,
+            Some("zsh") => Shell::Zsh,
+            Some("fish") => Shell::Fish,
+            Some("nu") => Shell::Nu,
+            _ => os.get_shell(),
+        }
+    }
+
+    fn program(&self) -> &'static str {
+        match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+            Shell::Nu => "nu",
+            Shell::Cmd => "cmd",
+        }
+    }
+
+    // Le répertoire temporaire retourné contient les fichiers d'initialisation
+    // et doit vivre aussi longtemps que le shell.
+    pub fn command(
+        &self,
+        vars: &BTreeMap<String, String>,
+        prompt: &str,
+    ) -> Result<(Command, Option<TempDir>)> {
+        let mut cmd = Command::new(self.program());
+        for (k, v) in vars {
+            cmd.env(k, v);
+        }
+        // les fichiers de configuration de l'utilisateur peuvent modifier PATH :
+        // on réapplique l'environnement après les avoir chargés
+        match self {
+            Shell::Bash => {
+                let dir = tempdir()?;
+                let mut rc = String::from("[ -f ~/.bashrc ] && . ~/.bashrc\n");
+                rc.push_str(&export::export(Format::Posix, vars)?);
+                if !prompt.is_empty() {
+                    rc.push_str(&format!("PS1={}\"$PS1\"\n", posix_quote(prompt)));
+                }
+                let rc_path = dir.path().join("bashrc");
+                fs::write(&rc_path, rc)?;
+                cmd.arg("--rcfile").arg(rc_path);
+                Ok((cmd, Some(dir)))
+            }
+            Shell::Zsh => {
+                let dir = tempdir()?;
+                let zdotdir = env::var("ZDOTDIR")
+                    .ok()
+                    .or_else(|| env::var("HOME").ok())
+                    .unwrap_or_default();
+                let zdotdir = posix_quote(&zdotdir);
+                let own = posix_quote(&dir.path().to_string_lossy());
+                // le .zshenv de l'utilisateur peut déplacer ZDOTDIR (configuration XDG) : on retient
+                // sa valeur pour le .zshrc et on revient au répertoire temporaire
+                fs::write(
+                    dir.path().join(".zshenv"),
+                    format!(
+                        r#"_chenv_zdotdir={zdotdir}
+[ -f "$_chenv_zdotdir/.zshenv" ] && . "$_chenv_zdotdir/.zshenv"
+if [ -n "$ZDOTDIR" ] && [ "$ZDOTDIR" != {own} ]; then
+  _chenv_zdotdir=$ZDOTDIR
+fi
+ZDOTDIR={own}
+"#
+                    ),
+                )?;
+                let mut rc = String::from(
+                    r#"ZDOTDIR=$_chenv_zdotdir
+unset _chenv_zdotdir
+[ -f "$ZDOTDIR/.zshrc" ] && . "$ZDOTDIR/.zshrc"
+"#,
+                );
+                rc.push_str(&export::export(Format::Posix, vars)?);
+                if !prompt.is_empty() {
+                    rc.push_str(&format!("PROMPT={}\"$PROMPT\"\n", posix_quote(prompt)));
+                }
+                fs::write(dir.path().join(".zshrc"), rc)?;
+                cmd.env("ZDOTDIR", dir.path());
+                Ok((cmd, Some(dir)))
+            }
+            Shell::Fish => {
+                let mut init = export::export(Format::Fish, vars)?;
+                if !prompt.is_empty() {
+                    init.push_str(&format!(
+                        "functions -c fish_prompt __chenv_fish_prompt\nfunction fish_prompt; echo -n {}; __chenv_fish_prompt; end\n",
+                        fish_quote(prompt)
+                    ));
+                }
+                cmd.arg("--init-command").arg(init);
+                Ok((cmd, None))
+            }
+            Shell::Nu => {
+                let mut init = format!(
+                    "load-env (r#'{}'# | from json)\n",
+                    serde_json::to_string(vars)?
+                );
+                let path = Os::get().get_path();
+                if vars.contains_key(path) {
+                    init.push_str(&format!(
+                        "$env.{path} = ($env.{path} | split row (char esep))\n"
+                    ));
+                }
+                if !prompt.is_empty() {
+                    init.push_str(&format!(
+                        "$env.PROMPT_INDICATOR = {}\n",
+                        serde_json::to_string(&format!("{prompt}> "))?
+                    ));
+                }
+                cmd.arg("--execute").arg(init);
+                Ok((cmd, None))
+            }
+            Shell::Cmd => {
+                if !prompt.is_empty() {
+                    cmd.arg("/K").arg(format!("prompt {}$P$G", prompt));
+                }
+                Ok((cmd, None))
+            }
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Result, anyhow};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+// petit script servi par une url file://, utilisé comme ressource File
+pub struct Tool {
+    pub path: PathBuf,
+    pub url: Url,
+    pub sha256: String,
+}
+
+pub fn tool(dir: &Path) -> Result<Tool> {
+    let path = dir.join("tool.sh");
+    fs::write(&path, "echo tool")?;
+    Ok(Tool {
+        url: Url::from_file_path(&path).map_err(|()| anyhow!("Invalid path {:?}", path))?,
+        sha256: format!("{:x}", Sha256::digest("echo tool")),
+        path,
+    })
+}
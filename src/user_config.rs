@@ -0,0 +1,124 @@
+use std::{collections::HashMap, env, path::PathBuf, thread};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{http::Tls, resources::auth::Auth};
+
+// configuration propre à l'utilisateur ou à la machine, partagée par tous les dépôts
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct UserConf {
+    // active le store global dans le répertoire de cache de l'utilisateur
+    #[serde(default)]
+    pub global_store: bool,
+    // emplacement du store global (implique global_store)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store: Option<PathBuf>,
+    // nombre maximal de ressources installées en parallèle
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jobs: Option<usize>,
+    // réécriture des urls téléchargées, par exemple vers le proxy d'artefacts de l'entreprise
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rewrites: Vec<Rewrite>,
+    // identifiants par hôte, utilisés pour toutes les ressources téléchargées depuis cet hôte
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub auth: HashMap<String, Auth>,
+    #[serde(default)]
+    pub tls: Tls,
+    // proxy par défaut de tous les téléchargements, à la place de HTTP_PROXY et HTTPS_PROXY
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    // hôtes joints sans proxy, au format de NO_PROXY (par défaut la variable NO_PROXY)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_proxy: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Rewrite {
+    pub prefix: String,
+    pub replacement: String,
+}
+
+impl UserConf {
+    pub fn global_store(&self) -> Option<PathBuf> {
+        if let Some(store) = env::var_os("CHENV_STORE") {
+            return Some(PathBuf::from(store));
+        }
+        match &self.store {
+            Some(store) => Some(store.clone()),
+            None if self.global_store => {
+                dirs::cache_dir().map(|dir| dir.join("chenv").join("store"))
+            }
+            None => None,
+        }
+    }
+
+    // applique la règle dont le préfixe est le plus long, comme `url.<base>.insteadOf` de git
+    pub fn rewrite(&self, url: &str) -> String {
+        self.rewrites
+            .iter()
+            .filter(|rule| url.starts_with(&rule.prefix))
+            .max_by_key(|rule| rule.prefix.len())
+            .map(|rule| format!("{}{}", rule.replacement, &url[rule.prefix.len()..]))
+            .unwrap_or_else(|| url.to_string())
+    }
+
+    pub fn jobs(&self) -> usize {
+        env::var("CHENV_JOBS")
+            .ok()
+            .and_then(|jobs| jobs.parse().ok())
+            .or(self.jobs)
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+            .max(1)
+    }
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    match env::var_os("CHENV_CONFIG") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => dirs::config_dir().map(|dir| dir.join("chenv").join("config.yaml")),
+    }
+}
+
+pub fn read_user_config() -> Result<UserConf> {
+    match user_config_path() {
+        Some(path) if path.exists() => {
+            let file = std::fs::File::open(&path)?;
+            serde_yaml::from_reader(file).with_context(|| format!("Cannot read {:?}", path))
+        }
+        _ => Ok(UserConf::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::UserConf;
+
+    #[test]
+    fn test_rewrite() -> Result<()> {
+        let conf: UserConf = serde_yaml::from_str(
+            r#"
+rewrites:
+  - prefix: https://github.com/
+    replacement: https://artifactory.corp/github/
+  - prefix: https://github.com/derailed/
+    replacement: https://mirror.corp/k9s/
+"#,
+        )?;
+        assert_eq!(
+            conf.rewrite("https://github.com/kubernetes/kubectl.tar.gz"),
+            "https://artifactory.corp/github/kubernetes/kubectl.tar.gz"
+        );
+        assert_eq!(
+            conf.rewrite("https://github.com/derailed/k9s/k9s.tar.gz"),
+            "https://mirror.corp/k9s/k9s/k9s.tar.gz"
+        );
+        assert_eq!(
+            conf.rewrite("https://dl.k8s.io/kubectl"),
+            "https://dl.k8s.io/kubectl"
+        );
+        Ok(())
+    }
+}
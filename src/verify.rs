@@ -0,0 +1,69 @@
+use std::{collections::BTreeSet, path::absolute};
+
+use anyhow::{Result, bail};
+
+use crate::{
+    config::Conf,
+    lock::Lock,
+    resources::store::{self, Store},
+};
+
+// vérifie toutes les entrées des stores utilisés par la configuration ; avec fix, les entrées
+// corrompues sont supprimées puis les ressources de la configuration sont réinstallées
+pub fn verify(
+    conf: &Conf,
+    store: &Store,
+    lock: Option<&Lock>,
+    fix: bool,
+    jobs: usize,
+) -> Result<()> {
+    let environments = conf.environments(lock);
+    let mut stores = BTreeSet::from([absolute(store.default_location())?]);
+    if let Some(global) = store.global() {
+        stores.insert(absolute(global)?);
+    }
+    for (_, env, _) in &environments {
+        for resource in env.resources.iter().flat_map(|r| r.values()) {
+            for entry in resource.entries(store)? {
+                if let Some(parent) = absolute(entry)?.parent()
+                    && store::is_store(parent)
+                {
+                    stores.insert(parent.to_owned());
+                }
+            }
+        }
+    }
+    let mut corrupt = 0;
+    for s in &stores {
+        for entry in store::list_entries(s)? {
+            let Some(problems) = store::verify_entry(&entry)? else {
+                println!("Unverified: {:?} (no manifest)", entry);
+                continue;
+            };
+            if problems.is_empty() {
+                println!("OK: {:?}", entry);
+                continue;
+            }
+            println!("Corrupt: {:?} ({})", entry, problems.join(", "));
+            corrupt += 1;
+            if fix {
+                let _lock = store::lock_entry(&entry)?;
+                store::remove_entry(&entry)?;
+            }
+        }
+    }
+    if corrupt == 0 {
+        return Ok(());
+    }
+    if !fix {
+        bail!(
+            "{} corrupt store entries, run `chenv verify --fix` to repair them",
+            corrupt
+        );
+    }
+    for (_, env, lock) in environments {
+        env.ensure_resources(store, lock, jobs)?;
+    }
+    println!("Repaired {} store entries", corrupt);
+    Ok(())
+}